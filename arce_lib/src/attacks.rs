@@ -1,28 +1,40 @@
 use crate::{
-    attacks::{
-        bishops::gen_bishop_attacks,
-        magics::{
-            bishop::{BISHOP_MAGIC_NUMBERS, BISHOP_RELEVANT_BITS},
-            rook::{ROOK_MAGIC_NUMBERS, ROOK_RELEVANT_BITS},
-        },
-        rooks::gen_rook_attacks,
+    attacks::magics::{
+        bishop::{BISHOP_MAGIC_NUMBERS, BISHOP_RELEVANT_BITS},
+        rook::{ROOK_MAGIC_NUMBERS, ROOK_RELEVANT_BITS},
     },
     bitboard::BitBoard,
+    piece::{Piece, BLACK_PIECES, WHITE_PIECES},
     position::Position,
     side::Side,
-    square::{Square, SQUARES},
+    square::Square,
 };
 
 use self::{
-    bishops::mask_bishop_attacks, kings::mask_king_attacks, knights::mask_knight_attacks,
-    pawns::mask_pawn_attacks, rooks::mask_rook_attacks,
+    bishops::mask_bishop_attacks,
+    geometry::{
+        direction, distance, mask_adjacent_files, mask_anti_diagonal, mask_diagonal, mask_file,
+        mask_king_ring, mask_passed_pawn, mask_rank, mask_ray,
+    },
+    kings::mask_king_attacks,
+    knights::mask_knight_attacks,
+    pawns::mask_pawn_attacks,
+    rooks::mask_rook_attacks,
 };
 
+#[cfg(all(feature = "pext", target_arch = "x86_64"))]
+use self::pext::{PextTable, BISHOP_TABLE_SIZE, ROOK_TABLE_SIZE};
+
+pub use self::geometry::Direction;
+
 mod bishops;
+mod geometry;
 mod kings;
 mod knights;
 mod magics;
 mod pawns;
+#[cfg(all(feature = "pext", target_arch = "x86_64"))]
+mod pext;
 mod rooks;
 
 #[allow(clippy::unreadable_literal)]
@@ -34,6 +46,8 @@ const NON_AB_FILE: BitBoard = BitBoard(18229723555195321596);
 #[allow(clippy::unreadable_literal)]
 const NON_GH_FILE: BitBoard = BitBoard(4557430888798830399);
 
+/// Attack tables for every piece together with board geometry tables. They are generated at
+/// compile time into the `ATTACKS` static, so there is never a need to build another copy.
 pub struct Attacks {
     w_pawn: [BitBoard; 64],
     b_pawn: [BitBoard; 64],
@@ -41,72 +55,245 @@ pub struct Attacks {
     king: [BitBoard; 64],
     bishop_masks: [BitBoard; 64],
     rook_masks: [BitBoard; 64],
-    bishop_attacks: Box<[[BitBoard; 512]; 64]>,
-    rook_attacks: Box<[[BitBoard; 4096]]>,
+    bishop_attacks: [[BitBoard; 512]; 64],
+    rook_attacks: [[BitBoard; 4096]; 64],
+    /// Bishop and rook tables used instead of the magic ones when the CPU has BMI2
+    #[cfg(all(feature = "pext", target_arch = "x86_64"))]
+    pext_bishop: PextTable<BISHOP_TABLE_SIZE>,
+    #[cfg(all(feature = "pext", target_arch = "x86_64"))]
+    pext_rook: PextTable<ROOK_TABLE_SIZE>,
+
+    // Geometry
+    between: [[BitBoard; 64]; 64],
+    line: [[BitBoard; 64]; 64],
+    distance: [[u8; 64]; 64],
+    direction: [[Option<Direction>; 64]; 64],
+    files: [BitBoard; 8],
+    ranks: [BitBoard; 8],
+    adjacent_files: [BitBoard; 8],
+    diagonals: [BitBoard; 64],
+    anti_diagonals: [BitBoard; 64],
+    passed_pawn: [[BitBoard; 64]; 2],
+    king_ring: [BitBoard; 64],
+}
+
+/// The attack and geometry tables, generated at compile time
+pub static ATTACKS: Attacks = Attacks::new();
+
+/// Knight attacks from `ATTACKS`, for code that does not pass an `&Attacks` around
+#[inline]
+pub fn knight_attacks(square: Square) -> BitBoard {
+    ATTACKS.get_knight_attacks(square)
+}
+
+/// King attacks from `ATTACKS`
+#[inline]
+pub fn king_attacks(square: Square) -> BitBoard {
+    ATTACKS.get_king_attacks(square)
+}
+
+/// Squares a pawn of `side` on `square` attacks, from `ATTACKS`
+#[inline]
+pub fn pawn_attacks(side: Side, square: Square) -> BitBoard {
+    match side {
+        Side::White => ATTACKS.get_w_pawn_attacks(square),
+        Side::Black => ATTACKS.get_b_pawn_attacks(square),
+    }
+}
+
+/// Bishop attacks from `ATTACKS`
+#[inline]
+pub fn bishop_attacks(square: Square, occupancy: BitBoard) -> BitBoard {
+    ATTACKS.get_bishop_attacks(square, occupancy)
+}
+
+/// Rook attacks from `ATTACKS`
+#[inline]
+pub fn rook_attacks(square: Square, occupancy: BitBoard) -> BitBoard {
+    ATTACKS.get_rook_attacks(square, occupancy)
+}
+
+/// Queen attacks from `ATTACKS`
+#[inline]
+pub fn queen_attacks(square: Square, occupancy: BitBoard) -> BitBoard {
+    ATTACKS.get_queen_attacks(square, occupancy)
+}
+
+const BISHOP_DIRECTIONS: [Direction; 4] = [
+    Direction::NorthEast,
+    Direction::SouthEast,
+    Direction::SouthWest,
+    Direction::NorthWest,
+];
+const ROOK_DIRECTIONS: [Direction; 4] = [
+    Direction::North,
+    Direction::East,
+    Direction::South,
+    Direction::West,
+];
+
+/// Rays from every square in every direction, indexed by direction then square
+const RAYS: [[BitBoard; 64]; 8] = {
+    let directions = [BISHOP_DIRECTIONS, ROOK_DIRECTIONS];
+    let mut rays = [[BitBoard::empty(); 64]; 8];
+    let mut i = 0;
+    while i < 64 {
+        let mut d = 0;
+        while d < 8 {
+            let dir = directions[d / 4][d % 4];
+            rays[dir as usize][i] = mask_ray(Square::from_u8_unchecked(i as u8), dir);
+            d += 1;
+        }
+        i += 1;
+    }
+    rays
+};
+
+/// Slider attacks along the given directions, stopping at the first blocker of each ray. This
+/// gives the same result as stepping square by square, in far fewer steps of the compile time
+/// evaluator.
+const fn ray_attacks(
+    rays: &[[BitBoard; 64]; 8],
+    square: usize,
+    occupancy: u64,
+    directions: &[Direction; 4],
+) -> BitBoard {
+    let mut attacks = 0;
+    let mut d = 0;
+    while d < 4 {
+        let dir = directions[d];
+        let ray = rays[dir as usize][square].0;
+        let blockers = ray & occupancy;
+        attacks |= if blockers == 0 {
+            ray
+        } else if dir.offset() > 0 {
+            ray ^ rays[dir as usize][blockers.trailing_zeros() as usize].0
+        } else {
+            ray ^ rays[dir as usize][63 - blockers.leading_zeros() as usize].0
+        };
+        d += 1;
+    }
+    BitBoard(attacks)
 }
 
 impl Attacks {
-    pub fn gen() -> Attacks {
-        // Leapers
-        let mut w_pawn = [BitBoard::empty(); 64];
-        let mut b_pawn = [BitBoard::empty(); 64];
-        let mut knight = [BitBoard::empty(); 64];
-        let mut king = [BitBoard::empty(); 64];
-
-        // Sliders
-        let mut bishop_masks = [BitBoard::empty(); 64];
-        let mut rook_masks = [BitBoard::empty(); 64];
-        let mut bishop_attacks = Box::new([[BitBoard::empty(); 512]; 64]);
-        let mut rook_attacks = vec![[BitBoard::empty(); 4096]; 64].into_boxed_slice();
-
-        SQUARES.iter().for_each(|&square| {
-            let i = square as usize;
-            w_pawn[i] = mask_pawn_attacks(square, Side::White);
-            b_pawn[i] = mask_pawn_attacks(square, Side::Black);
-            knight[i] = mask_knight_attacks(square);
-            king[i] = mask_king_attacks(square);
+    const fn new() -> Attacks {
+        let mut attacks = Attacks {
+            w_pawn: [BitBoard::empty(); 64],
+            b_pawn: [BitBoard::empty(); 64],
+            knight: [BitBoard::empty(); 64],
+            king: [BitBoard::empty(); 64],
+            bishop_masks: [BitBoard::empty(); 64],
+            rook_masks: [BitBoard::empty(); 64],
+            bishop_attacks: [[BitBoard::empty(); 512]; 64],
+            rook_attacks: [[BitBoard::empty(); 4096]; 64],
+            #[cfg(all(feature = "pext", target_arch = "x86_64"))]
+            pext_bishop: PextTable::empty(),
+            #[cfg(all(feature = "pext", target_arch = "x86_64"))]
+            pext_rook: PextTable::empty(),
+            between: [[BitBoard::empty(); 64]; 64],
+            line: [[BitBoard::empty(); 64]; 64],
+            distance: [[0; 64]; 64],
+            direction: [[None; 64]; 64],
+            files: [BitBoard::empty(); 8],
+            ranks: [BitBoard::empty(); 8],
+            adjacent_files: [BitBoard::empty(); 8],
+            diagonals: [BitBoard::empty(); 64],
+            anti_diagonals: [BitBoard::empty(); 64],
+            passed_pawn: [[BitBoard::empty(); 64]; 2],
+            king_ring: [BitBoard::empty(); 64],
+        };
+
+        let rays = RAYS;
+
+        let mut i = 0;
+        while i < 8 {
+            attacks.files[i] = mask_file(i as u8);
+            attacks.ranks[i] = mask_rank(i as u8);
+            attacks.adjacent_files[i] = mask_adjacent_files(i as u8);
+            i += 1;
+        }
+
+        let mut i = 0;
+        while i < 64 {
+            let square = Square::from_u8_unchecked(i as u8);
+
+            // Leapers
+            attacks.w_pawn[i] = mask_pawn_attacks(square, Side::White);
+            attacks.b_pawn[i] = mask_pawn_attacks(square, Side::Black);
+            attacks.knight[i] = mask_knight_attacks(square);
+            attacks.king[i] = mask_king_attacks(square);
 
             // Bishops
-            bishop_masks[i] = mask_bishop_attacks(square);
-
-            let attack_mask = bishop_masks[i];
-            let relevant_bits_count = attack_mask.count_bits();
-            let occupancy_indices = 1 << relevant_bits_count;
-            for index in 0..occupancy_indices {
-                let occupancy = attack_mask.set_occupancy(index, relevant_bits_count);
-                let magic_index = (occupancy * BISHOP_MAGIC_NUMBERS[square as usize])
-                    >> (64 - BISHOP_RELEVANT_BITS[square as usize]);
-
-                bishop_attacks[square as usize][magic_index.0 as usize] =
-                    gen_bishop_attacks(square, occupancy);
+            let attack_mask = mask_bishop_attacks(square);
+            attacks.bishop_masks[i] = attack_mask;
+            #[cfg(all(feature = "pext", target_arch = "x86_64"))]
+            attacks.pext_bishop.start_square(square, attack_mask);
+            // Walk every subset of the mask
+            let mut occupancy: u64 = 0;
+            loop {
+                let magic_index = occupancy.wrapping_mul(BISHOP_MAGIC_NUMBERS[i])
+                    >> (64 - BISHOP_RELEVANT_BITS[i]);
+                let bishop_attacks = ray_attacks(&rays, i, occupancy, &BISHOP_DIRECTIONS);
+                attacks.bishop_attacks[i][magic_index as usize] = bishop_attacks;
+                #[cfg(all(feature = "pext", target_arch = "x86_64"))]
+                attacks.pext_bishop.push(bishop_attacks);
+                occupancy = occupancy.wrapping_sub(attack_mask.0) & attack_mask.0;
+                if occupancy == 0 {
+                    break;
+                }
             }
 
             // Rooks
-            rook_masks[i] = mask_rook_attacks(square);
-
-            let attack_mask = rook_masks[i];
-            let relevant_bits_count = attack_mask.count_bits();
-            let occupancy_indices = 1 << relevant_bits_count;
-            for index in 0..occupancy_indices {
-                let occupancy = attack_mask.set_occupancy(index, relevant_bits_count);
-                let magic_index = (occupancy * ROOK_MAGIC_NUMBERS[square as usize])
-                    >> (64 - ROOK_RELEVANT_BITS[square as usize]);
-
-                rook_attacks[square as usize][magic_index.0 as usize] =
-                    gen_rook_attacks(square, occupancy);
+            let attack_mask = mask_rook_attacks(square);
+            attacks.rook_masks[i] = attack_mask;
+            #[cfg(all(feature = "pext", target_arch = "x86_64"))]
+            attacks.pext_rook.start_square(square, attack_mask);
+            let mut occupancy: u64 = 0;
+            loop {
+                let magic_index =
+                    occupancy.wrapping_mul(ROOK_MAGIC_NUMBERS[i]) >> (64 - ROOK_RELEVANT_BITS[i]);
+                let rook_attacks = ray_attacks(&rays, i, occupancy, &ROOK_DIRECTIONS);
+                attacks.rook_attacks[i][magic_index as usize] = rook_attacks;
+                #[cfg(all(feature = "pext", target_arch = "x86_64"))]
+                attacks.pext_rook.push(rook_attacks);
+                occupancy = occupancy.wrapping_sub(attack_mask.0) & attack_mask.0;
+                if occupancy == 0 {
+                    break;
+                }
             }
-        });
-
-        Attacks {
-            w_pawn,
-            b_pawn,
-            knight,
-            king,
-            bishop_masks,
-            rook_masks,
-            bishop_attacks,
-            rook_attacks,
+
+            // Geometry
+            let mut j = 0;
+            while j < 64 {
+                let other = Square::from_u8_unchecked(j as u8);
+                let dir = direction(square, other);
+                if let Some(dir) = dir {
+                    // The ray past `other` and `other` itself are not between the squares
+                    let ray = &rays[dir as usize];
+                    attacks.between[i][j] = BitBoard(ray[i].0 & !ray[j].0 & !(1 << j));
+                    attacks.line[i][j] =
+                        BitBoard(ray[i].0 | rays[dir.opposite() as usize][i].0 | 1 << i);
+                }
+                attacks.distance[i][j] = distance(square, other);
+                attacks.direction[i][j] = dir;
+                j += 1;
+            }
+            attacks.diagonals[i] = mask_diagonal(square);
+            attacks.anti_diagonals[i] = mask_anti_diagonal(square);
+            attacks.passed_pawn[Side::White as usize][i] = mask_passed_pawn(square, Side::White);
+            attacks.passed_pawn[Side::Black as usize][i] = mask_passed_pawn(square, Side::Black);
+            attacks.king_ring[i] = mask_king_ring(square);
+
+            i += 1;
         }
+        attacks
+    }
+
+    /// Builds another copy of the tables at runtime, as callers did before `ATTACKS` existed
+    #[deprecated(note = "use the `ATTACKS` static, which is generated at compile time")]
+    pub fn gen() -> Attacks {
+        Attacks::new()
     }
 
     #[inline]
@@ -130,7 +317,27 @@ impl Attacks {
     }
 
     #[inline]
-    pub fn get_bishop_attacks(&self, square: Square, mut occupancy: BitBoard) -> BitBoard {
+    pub fn get_bishop_attacks(&self, square: Square, occupancy: BitBoard) -> BitBoard {
+        #[cfg(all(feature = "pext", target_arch = "x86_64"))]
+        if pext::has_bmi2() {
+            // Safe as the CPU has BMI2
+            return unsafe { self.pext_bishop.get(square, occupancy) };
+        }
+        self.get_magic_bishop_attacks(square, occupancy)
+    }
+
+    #[inline]
+    pub fn get_rook_attacks(&self, square: Square, occupancy: BitBoard) -> BitBoard {
+        #[cfg(all(feature = "pext", target_arch = "x86_64"))]
+        if pext::has_bmi2() {
+            // Safe as the CPU has BMI2
+            return unsafe { self.pext_rook.get(square, occupancy) };
+        }
+        self.get_magic_rook_attacks(square, occupancy)
+    }
+
+    #[inline]
+    fn get_magic_bishop_attacks(&self, square: Square, mut occupancy: BitBoard) -> BitBoard {
         occupancy &= self.bishop_masks[square as usize];
         occupancy *= BISHOP_MAGIC_NUMBERS[square as usize];
         occupancy >>= 64 - BISHOP_RELEVANT_BITS[square as usize];
@@ -139,7 +346,7 @@ impl Attacks {
     }
 
     #[inline]
-    pub fn get_rook_attacks(&self, square: Square, mut occupancy: BitBoard) -> BitBoard {
+    fn get_magic_rook_attacks(&self, square: Square, mut occupancy: BitBoard) -> BitBoard {
         occupancy &= self.rook_masks[square as usize];
         occupancy *= ROOK_MAGIC_NUMBERS[square as usize];
         occupancy >>= 64 - ROOK_RELEVANT_BITS[square as usize];
@@ -152,6 +359,75 @@ impl Attacks {
         self.get_bishop_attacks(square, occupancy) | self.get_rook_attacks(square, occupancy)
     }
 
+    /// Squares strictly between two squares on a shared rank, file or diagonal, or an empty
+    /// board if they are not aligned
+    #[inline]
+    pub fn between(&self, a: Square, b: Square) -> BitBoard {
+        self.between[a as usize][b as usize]
+    }
+
+    /// The whole rank, file or diagonal through two squares, including both, or an empty board
+    /// if they are not aligned
+    #[inline]
+    pub fn line(&self, a: Square, b: Square) -> BitBoard {
+        self.line[a as usize][b as usize]
+    }
+
+    /// Number of king steps from one square to another
+    #[inline]
+    pub fn distance(&self, a: Square, b: Square) -> u8 {
+        self.distance[a as usize][b as usize]
+    }
+
+    /// Direction from `a` towards `b`, or None if they are the same square or not aligned
+    #[inline]
+    pub fn direction(&self, a: Square, b: Square) -> Option<Direction> {
+        self.direction[a as usize][b as usize]
+    }
+
+    /// The file of the square
+    #[inline]
+    pub fn file_mask(&self, square: Square) -> BitBoard {
+        self.files[square as usize & 0b111]
+    }
+
+    /// The rank of the square
+    #[inline]
+    pub fn rank_mask(&self, square: Square) -> BitBoard {
+        self.ranks[square as usize >> 3]
+    }
+
+    /// The files either side of the square's file, for isolated pawn checks
+    #[inline]
+    pub fn adjacent_files_mask(&self, square: Square) -> BitBoard {
+        self.adjacent_files[square as usize & 0b111]
+    }
+
+    /// The a1-h8 direction diagonal through the square, including the square
+    #[inline]
+    pub fn diagonal_mask(&self, square: Square) -> BitBoard {
+        self.diagonals[square as usize]
+    }
+
+    /// The a8-h1 direction diagonal through the square, including the square
+    #[inline]
+    pub fn anti_diagonal_mask(&self, square: Square) -> BitBoard {
+        self.anti_diagonals[square as usize]
+    }
+
+    /// Squares in front of a pawn of `side` on its own and the adjacent files, which must hold
+    /// no enemy pawn for the pawn to be passed
+    #[inline]
+    pub fn passed_pawn_mask(&self, side: Side, square: Square) -> BitBoard {
+        self.passed_pawn[side as usize][square as usize]
+    }
+
+    /// The king's square and the squares around it
+    #[inline]
+    pub fn king_ring(&self, square: Square) -> BitBoard {
+        self.king_ring[square as usize]
+    }
+
     #[inline]
     pub fn is_square_attacked(&self, pos: &Position, square: Square, by: Side) -> bool {
         if by == Side::White {
@@ -175,12 +451,63 @@ impl Attacks {
         }
     }
 
+    /// Returns the pieces of both sides attacking `square`, with sliders blocked by `occupancy`
+    /// rather than the position's own occupancy. Passing an occupancy with pieces removed finds
+    /// the sliders behind them; the removed pieces themselves are only left out of the result
+    /// when masked off with the same occupancy.
+    #[inline]
+    pub fn attackers_to(&self, pos: &Position, square: Square, occupancy: BitBoard) -> BitBoard {
+        let diagonal = pos.w_bishops | pos.b_bishops | pos.w_queens | pos.b_queens;
+        let orthogonal = pos.w_rooks | pos.b_rooks | pos.w_queens | pos.b_queens;
+        // A pawn attacks the squares a pawn of the other colour would attack from there, and back
+        (self.get_b_pawn_attacks(square) & pos.w_pawns)
+            | (self.get_w_pawn_attacks(square) & pos.b_pawns)
+            | (self.get_knight_attacks(square) & (pos.w_knights | pos.b_knights))
+            | (self.get_king_attacks(square) & (pos.w_king | pos.b_king))
+            | (self.get_bishop_attacks(square, occupancy) & diagonal)
+            | (self.get_rook_attacks(square, occupancy) & orthogonal)
+    }
+
+    /// Returns every square attacked by a piece of `side`, whether or not it is empty or holds a
+    /// piece of either side
+    pub fn attacked_by(&self, pos: &Position, side: Side) -> BitBoard {
+        let (pieces, mut attacked) = match side {
+            Side::White => (
+                WHITE_PIECES,
+                pos.w_pawns.shift_north_west() | pos.w_pawns.shift_north_east(),
+            ),
+            Side::Black => (
+                BLACK_PIECES,
+                pos.b_pawns.shift_south_east() | pos.b_pawns.shift_south_west(),
+            ),
+        };
+        for &piece in &pieces[1..] {
+            for square in pos.get_piece_bitboard(piece) {
+                attacked |= match piece {
+                    Piece::WKnight | Piece::BKnight => self.get_knight_attacks(square),
+                    Piece::WBishop | Piece::BBishop => {
+                        self.get_bishop_attacks(square, pos.all_occupancies)
+                    }
+                    Piece::WRook | Piece::BRook => {
+                        self.get_rook_attacks(square, pos.all_occupancies)
+                    }
+                    Piece::WQueen | Piece::BQueen => {
+                        self.get_queen_attacks(square, pos.all_occupancies)
+                    }
+                    _ => self.get_king_attacks(square),
+                };
+            }
+        }
+        attacked
+    }
+
     pub fn print_attacked_squares(&self, pos: &Position, side: Side) {
+        let attacked = self.attacked_by(pos, side);
         for r in (0..8).rev() {
             print!(" {}", r + 1);
             for f in 0..8 {
                 let square = Square::from_fr_unchecked(f, r);
-                let is_attacked = if self.is_square_attacked(pos, square, side) {
+                let is_attacked = if attacked.get_bit(square).is_not_empty() {
                     1
                 } else {
                     0
@@ -193,3 +520,154 @@ impl Attacks {
         println!("   A B C D E F G H");
     }
 }
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        bitboard::BitBoard,
+        side::Side,
+        square::{Square, SQUARES},
+        utils::fen::{parse_fen, CMK_POSITION, KILLER_POSITION, START_POSITION, TRICKY_POSITION},
+    };
+
+    use super::{Direction, ATTACKS};
+
+    #[test]
+    fn test_geometry() {
+        for &a in SQUARES.iter() {
+            for &b in SQUARES.iter() {
+                let between = ATTACKS.between(a, b);
+                let line = ATTACKS.line(a, b);
+                match ATTACKS.direction(a, b) {
+                    Some(dir) => {
+                        // Stepping from a in the direction passes the between squares, then b
+                        let mut walked = BitBoard::empty();
+                        let mut square = a as i8 + dir.offset();
+                        while square != b as i8 {
+                            walked.set_bit(Square::from_u8(square as u8).unwrap());
+                            square += dir.offset();
+                        }
+                        assert_eq!(between, walked, "{} {}", a, b);
+                        assert_eq!(ATTACKS.distance(a, b), between.count_bits() + 1);
+                        assert_eq!(line, ATTACKS.line(b, a));
+                        assert!(line.get_bit(a).is_not_empty() && line.get_bit(b).is_not_empty());
+                        assert_eq!(line & between, between);
+                        let is_orthogonal = ATTACKS.rank_mask(a) == ATTACKS.rank_mask(b)
+                            || ATTACKS.file_mask(a) == ATTACKS.file_mask(b);
+                        assert_eq!(dir.is_orthogonal(), is_orthogonal);
+                    }
+                    None => {
+                        assert!(between.is_empty() && line.is_empty(), "{} {}", a, b);
+                    }
+                }
+            }
+        }
+
+        assert_eq!(
+            ATTACKS.direction(Square::E4, Square::H7),
+            Some(Direction::NorthEast)
+        );
+        assert_eq!(
+            ATTACKS.direction(Square::E4, Square::E1),
+            Some(Direction::South)
+        );
+        assert_eq!(ATTACKS.direction(Square::E4, Square::F6), None);
+        assert_eq!(ATTACKS.distance(Square::A1, Square::H8), 7);
+        assert_eq!(ATTACKS.distance(Square::B1, Square::C3), 2);
+        assert_eq!(ATTACKS.line(Square::B2, Square::D4).count_bits(), 8);
+        assert_eq!(ATTACKS.line(Square::C1, Square::A3).count_bits(), 3);
+        assert_eq!(ATTACKS.file_mask(Square::C5).count_bits(), 8);
+        assert_eq!(ATTACKS.rank_mask(Square::C5), ATTACKS.rank_mask(Square::H5));
+        assert_eq!(ATTACKS.diagonal_mask(Square::C1).count_bits(), 6);
+        assert_eq!(ATTACKS.anti_diagonal_mask(Square::C1).count_bits(), 3);
+        assert_eq!(ATTACKS.adjacent_files_mask(Square::A4).count_bits(), 8);
+        assert_eq!(ATTACKS.adjacent_files_mask(Square::E4).count_bits(), 16);
+        assert_eq!(
+            ATTACKS
+                .passed_pawn_mask(Side::White, Square::E4)
+                .count_bits(),
+            12
+        );
+        assert_eq!(
+            ATTACKS
+                .passed_pawn_mask(Side::Black, Square::A7)
+                .count_bits(),
+            12
+        );
+        assert_eq!(ATTACKS.king_ring(Square::A1).count_bits(), 4);
+        assert_eq!(ATTACKS.king_ring(Square::E4).count_bits(), 9);
+    }
+
+    #[cfg(all(feature = "pext", target_arch = "x86_64"))]
+    #[test]
+    fn test_pext_matches_magics() {
+        if !super::pext::has_bmi2() {
+            // Without BMI2 the magic tables are used, so there is nothing to compare
+            return;
+        }
+        for &square in SQUARES.iter() {
+            for &(mask, is_bishop) in [
+                (ATTACKS.bishop_masks[square as usize], true),
+                (ATTACKS.rook_masks[square as usize], false),
+            ]
+            .iter()
+            {
+                let bits = mask.count_bits();
+                for index in 0..1 << bits {
+                    let subset = mask.set_occupancy(index, bits);
+                    // Squares outside the mask must not change the result
+                    for &occupancy in [subset, subset | !mask].iter() {
+                        let (pext, magic) = if is_bishop {
+                            (
+                                ATTACKS.get_bishop_attacks(square, occupancy),
+                                ATTACKS.get_magic_bishop_attacks(square, occupancy),
+                            )
+                        } else {
+                            (
+                                ATTACKS.get_rook_attacks(square, occupancy),
+                                ATTACKS.get_magic_rook_attacks(square, occupancy),
+                            )
+                        };
+                        assert_eq!(pext, magic, "{} with\n{}", square, occupancy);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_attack_maps() {
+        for fen in [
+            START_POSITION,
+            TRICKY_POSITION,
+            KILLER_POSITION,
+            CMK_POSITION,
+        ]
+        .iter()
+        {
+            let pos = parse_fen(fen).unwrap();
+            for &side in [Side::White, Side::Black].iter() {
+                let attacked = ATTACKS.attacked_by(&pos, side);
+                for &square in SQUARES.iter() {
+                    let attackers = ATTACKS.attackers_to(&pos, square, pos.all_occupancies)
+                        & pos.get_occupancy_bitboard(side);
+                    let is_attacked = ATTACKS.is_square_attacked(&pos, square, side);
+                    assert_eq!(
+                        attackers.is_not_empty(),
+                        is_attacked,
+                        "{} in {}",
+                        square,
+                        fen
+                    );
+                    assert_eq!(
+                        attacked.get_bit(square).is_not_empty(),
+                        is_attacked,
+                        "{} in {}",
+                        square,
+                        fen
+                    );
+                }
+            }
+        }
+    }
+}
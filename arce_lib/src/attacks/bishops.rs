@@ -1,14 +1,14 @@
 use crate::{bitboard::BitBoard, square::Square};
 
-pub fn mask_bishop_attacks(square: Square) -> BitBoard {
-    let mut attacks = BitBoard::empty();
+pub const fn mask_bishop_attacks(square: Square) -> BitBoard {
+    let mut attacks = 0;
 
-    let (tf, tr): (i8, i8) = square.into();
+    let (tf, tr) = ((square as u8 & 0b111) as i8, (square as u8 / 8) as i8);
 
     let mut f = tf + 1;
     let mut r = tr + 1;
     while f <= 6 && r <= 6 {
-        attacks |= BitBoard(1) << (r * 8 + f) as u8;
+        attacks |= 1 << (r * 8 + f);
         f += 1;
         r += 1;
     }
@@ -16,7 +16,7 @@ pub fn mask_bishop_attacks(square: Square) -> BitBoard {
     f = tf + 1;
     r = tr - 1;
     while f <= 6 && r >= 1 {
-        attacks |= BitBoard(1) << (r * 8 + f) as u8;
+        attacks |= 1 << (r * 8 + f);
         f += 1;
         r -= 1;
     }
@@ -24,7 +24,7 @@ pub fn mask_bishop_attacks(square: Square) -> BitBoard {
     f = tf - 1;
     r = tr - 1;
     while f >= 1 && r >= 1 {
-        attacks |= BitBoard(1) << (r * 8 + f) as u8;
+        attacks |= 1 << (r * 8 + f);
         f -= 1;
         r -= 1;
     }
@@ -32,26 +32,26 @@ pub fn mask_bishop_attacks(square: Square) -> BitBoard {
     f = tf - 1;
     r = tr + 1;
     while f >= 1 && r <= 6 {
-        attacks |= BitBoard(1) << (r * 8 + f) as u8;
+        attacks |= 1 << (r * 8 + f);
         f -= 1;
         r += 1;
     }
 
-    attacks
+    BitBoard(attacks)
 }
 
-pub fn gen_bishop_attacks(square: Square, block: BitBoard) -> BitBoard {
-    let mut attacks = BitBoard::empty();
+pub const fn gen_bishop_attacks(square: Square, block: BitBoard) -> BitBoard {
+    let mut attacks = 0;
 
-    let (tf, tr): (i8, i8) = square.into();
+    let (tf, tr) = ((square as u8 & 0b111) as i8, (square as u8 / 8) as i8);
 
     let mut f = tf + 1;
     let mut r = tr + 1;
     while f <= 7 && r <= 7 {
-        let cb = BitBoard(1) << (r * 8 + f) as u8;
+        let cb = 1 << (r * 8 + f);
         attacks |= cb;
 
-        if (cb & block).is_not_empty() {
+        if cb & block.0 != 0 {
             break;
         }
 
@@ -62,10 +62,10 @@ pub fn gen_bishop_attacks(square: Square, block: BitBoard) -> BitBoard {
     f = tf + 1;
     r = tr - 1;
     while f <= 7 && r >= 0 {
-        let cb = BitBoard(1) << (r * 8 + f) as u8;
+        let cb = 1 << (r * 8 + f);
         attacks |= cb;
 
-        if (cb & block).is_not_empty() {
+        if cb & block.0 != 0 {
             break;
         }
 
@@ -76,10 +76,10 @@ pub fn gen_bishop_attacks(square: Square, block: BitBoard) -> BitBoard {
     f = tf - 1;
     r = tr - 1;
     while f >= 0 && r >= 0 {
-        let cb = BitBoard(1) << (r * 8 + f) as u8;
+        let cb = 1 << (r * 8 + f);
         attacks |= cb;
 
-        if (cb & block).is_not_empty() {
+        if cb & block.0 != 0 {
             break;
         }
 
@@ -90,10 +90,10 @@ pub fn gen_bishop_attacks(square: Square, block: BitBoard) -> BitBoard {
     f = tf - 1;
     r = tr + 1;
     while f >= 0 && r <= 7 {
-        let cb = BitBoard(1) << (r * 8 + f) as u8;
+        let cb = 1 << (r * 8 + f);
         attacks |= cb;
 
-        if (cb & block).is_not_empty() {
+        if cb & block.0 != 0 {
             break;
         }
 
@@ -101,5 +101,5 @@ pub fn gen_bishop_attacks(square: Square, block: BitBoard) -> BitBoard {
         r += 1;
     }
 
-    attacks
+    BitBoard(attacks)
 }
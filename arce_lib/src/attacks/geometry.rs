@@ -0,0 +1,189 @@
+use crate::{bitboard::BitBoard, side::Side, square::Square};
+
+use super::kings::mask_king_attacks;
+
+/// Compass direction from one square towards another on a shared rank, file or diagonal, with
+/// north towards the eighth rank
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    North,
+    NorthEast,
+    East,
+    SouthEast,
+    South,
+    SouthWest,
+    West,
+    NorthWest,
+}
+
+impl Direction {
+    /// Change in square index for one step in this direction
+    #[inline]
+    pub const fn offset(self) -> i8 {
+        match self {
+            Direction::North => 8,
+            Direction::NorthEast => 9,
+            Direction::East => 1,
+            Direction::SouthEast => -7,
+            Direction::South => -8,
+            Direction::SouthWest => -9,
+            Direction::West => -1,
+            Direction::NorthWest => 7,
+        }
+    }
+
+    /// The direction pointing the other way
+    #[inline]
+    pub const fn opposite(self) -> Direction {
+        match self {
+            Direction::North => Direction::South,
+            Direction::NorthEast => Direction::SouthWest,
+            Direction::East => Direction::West,
+            Direction::SouthEast => Direction::NorthWest,
+            Direction::South => Direction::North,
+            Direction::SouthWest => Direction::NorthEast,
+            Direction::West => Direction::East,
+            Direction::NorthWest => Direction::SouthEast,
+        }
+    }
+
+    /// Returns true for directions along a rank or file
+    #[inline]
+    pub const fn is_orthogonal(self) -> bool {
+        matches!(
+            self,
+            Direction::North | Direction::East | Direction::South | Direction::West
+        )
+    }
+}
+
+/// Direction from `a` towards `b`, None if they are the same square or not aligned
+pub const fn direction(a: Square, b: Square) -> Option<Direction> {
+    let (af, ar) = file_rank(a);
+    let (bf, br) = file_rank(b);
+    let (df, dr) = (bf - af, br - ar);
+    if (df == 0 && dr == 0) || (df != 0 && dr != 0 && df.abs() != dr.abs()) {
+        return None;
+    }
+    match (df.signum(), dr.signum()) {
+        (0, 1) => Some(Direction::North),
+        (1, 1) => Some(Direction::NorthEast),
+        (1, 0) => Some(Direction::East),
+        (1, -1) => Some(Direction::SouthEast),
+        (0, -1) => Some(Direction::South),
+        (-1, -1) => Some(Direction::SouthWest),
+        (-1, 0) => Some(Direction::West),
+        _ => Some(Direction::NorthWest),
+    }
+}
+
+/// Number of king steps between two squares
+pub const fn distance(a: Square, b: Square) -> u8 {
+    let (af, ar) = file_rank(a);
+    let (bf, br) = file_rank(b);
+    let (df, dr) = ((bf - af).abs(), (br - ar).abs());
+    if df > dr {
+        df as u8
+    } else {
+        dr as u8
+    }
+}
+
+/// Squares from `square` to the edge of the board in the given direction, not including
+/// `square`
+pub const fn mask_ray(square: Square, dir: Direction) -> BitBoard {
+    let (df, dr) = match dir {
+        Direction::North => (0, 1),
+        Direction::NorthEast => (1, 1),
+        Direction::East => (1, 0),
+        Direction::SouthEast => (1, -1),
+        Direction::South => (0, -1),
+        Direction::SouthWest => (-1, -1),
+        Direction::West => (-1, 0),
+        Direction::NorthWest => (-1, 1),
+    };
+    let (mut f, mut r) = file_rank(square);
+    let mut ray = 0;
+    loop {
+        f += df;
+        r += dr;
+        if f < 0 || f > 7 || r < 0 || r > 7 {
+            return BitBoard(ray);
+        }
+        ray |= 1 << (8 * r + f);
+    }
+}
+
+pub const fn mask_file(file: u8) -> BitBoard {
+    BitBoard(0x0101_0101_0101_0101 << file)
+}
+
+pub const fn mask_rank(rank: u8) -> BitBoard {
+    BitBoard(0xff << (8 * rank))
+}
+
+/// The files either side of `file`, without `file` itself
+pub const fn mask_adjacent_files(file: u8) -> BitBoard {
+    let mut mask = 0;
+    if file > 0 {
+        mask |= mask_file(file - 1).0;
+    }
+    if file < 7 {
+        mask |= mask_file(file + 1).0;
+    }
+    BitBoard(mask)
+}
+
+/// The a1-h8 direction diagonal through the square, including the square
+pub const fn mask_diagonal(square: Square) -> BitBoard {
+    let (sf, sr) = file_rank(square);
+    let mut mask = 0;
+    let mut i = 0;
+    while i < 64 {
+        let (f, r) = ((i & 0b111) as i8, (i / 8) as i8);
+        if f - r == sf - sr {
+            mask |= 1 << i;
+        }
+        i += 1;
+    }
+    BitBoard(mask)
+}
+
+/// The a8-h1 direction diagonal through the square, including the square
+pub const fn mask_anti_diagonal(square: Square) -> BitBoard {
+    let (sf, sr) = file_rank(square);
+    let mut mask = 0;
+    let mut i = 0;
+    while i < 64 {
+        let (f, r) = ((i & 0b111) as i8, (i / 8) as i8);
+        if f + r == sf + sr {
+            mask |= 1 << i;
+        }
+        i += 1;
+    }
+    BitBoard(mask)
+}
+
+/// Squares in front of a pawn of `side` on its own and the adjacent files. The pawn is passed
+/// when no enemy pawn stands on them.
+pub const fn mask_passed_pawn(square: Square, side: Side) -> BitBoard {
+    let (file, rank) = (square as u8 & 0b111, square as u8 / 8);
+    let files = mask_file(file).0 | mask_adjacent_files(file).0;
+    let front = match side {
+        Side::White if rank < 7 => !0 << (8 * (rank + 1)),
+        Side::Black if rank > 0 => !0 >> (8 * (8 - rank)),
+        _ => 0,
+    };
+    BitBoard(files & front)
+}
+
+/// The king's square and the squares around it
+pub const fn mask_king_ring(square: Square) -> BitBoard {
+    BitBoard(mask_king_attacks(square).0 | 1 << square as u8)
+}
+
+/// (File, Rank)
+#[inline]
+const fn file_rank(square: Square) -> (i8, i8) {
+    ((square as u8 & 0b111) as i8, (square as u8 / 8) as i8)
+}
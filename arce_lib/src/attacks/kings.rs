@@ -2,31 +2,19 @@ use crate::{bitboard::BitBoard, square::Square};
 
 use super::{NON_A_FILE, NON_H_FILE};
 
-pub fn mask_king_attacks(square: Square) -> BitBoard {
-    let mut attacks = BitBoard::empty();
-    let sb = BitBoard::from(square);
+pub const fn mask_king_attacks(square: Square) -> BitBoard {
+    let mut attacks = 0;
+    let sb = 1_u64 << square as u8;
 
-    if ((sb << 1) & NON_A_FILE).is_not_empty() {
-        attacks |= sb << 1;
-    }
-    if ((sb << 7) & NON_H_FILE).is_not_empty() {
-        attacks |= sb << 7;
-    }
+    attacks |= (sb << 1) & NON_A_FILE.0;
+    attacks |= (sb << 7) & NON_H_FILE.0;
     attacks |= sb << 8;
-    if ((sb << 9) & NON_A_FILE).is_not_empty() {
-        attacks |= sb << 9;
-    }
+    attacks |= (sb << 9) & NON_A_FILE.0;
 
-    if ((sb >> 1) & NON_H_FILE).is_not_empty() {
-        attacks |= sb >> 1;
-    }
+    attacks |= (sb >> 1) & NON_H_FILE.0;
     attacks |= sb >> 8;
-    if ((sb >> 7) & NON_A_FILE).is_not_empty() {
-        attacks |= sb >> 7;
-    }
-    if ((sb >> 9) & NON_H_FILE).is_not_empty() {
-        attacks |= sb >> 9;
-    }
+    attacks |= (sb >> 7) & NON_A_FILE.0;
+    attacks |= (sb >> 9) & NON_H_FILE.0;
 
-    attacks
+    BitBoard(attacks)
 }
@@ -120,8 +120,8 @@ pub fn find_bishop_magic_number(square: Square, relevant_bits: u8) -> u64 {
 
             // If magic index works
             if used_attacks[magic_index as usize] == BitBoard::empty() {
-                used_attacks[magic_index as usize] = attacks[index as usize];
-            } else if used_attacks[magic_index as usize] != attacks[index as usize] {
+                used_attacks[magic_index as usize] = attacks[index];
+            } else if used_attacks[magic_index as usize] != attacks[index] {
                 fail = true;
             }
             index += 1;
@@ -2,27 +2,19 @@ use crate::{bitboard::BitBoard, side::Side, square::Square};
 
 use super::{NON_A_FILE, NON_H_FILE};
 
-pub fn mask_pawn_attacks(square: Square, side: Side) -> BitBoard {
-    let mut attacks = BitBoard::empty();
-    let sb = BitBoard::from(square);
+pub const fn mask_pawn_attacks(square: Square, side: Side) -> BitBoard {
+    let mut attacks = 0;
+    let sb = 1_u64 << square as u8;
 
     match side {
         Side::White => {
-            if ((sb << 7) & NON_H_FILE).is_not_empty() {
-                attacks |= sb << 7;
-            }
-            if ((sb << 9) & NON_A_FILE).is_not_empty() {
-                attacks |= sb << 9;
-            }
+            attacks |= (sb << 7) & NON_H_FILE.0;
+            attacks |= (sb << 9) & NON_A_FILE.0;
         }
         Side::Black => {
-            if ((sb >> 7) & NON_A_FILE).is_not_empty() {
-                attacks |= sb >> 7;
-            }
-            if ((sb >> 9) & NON_H_FILE).is_not_empty() {
-                attacks |= sb >> 9;
-            }
+            attacks |= (sb >> 7) & NON_A_FILE.0;
+            attacks |= (sb >> 9) & NON_H_FILE.0;
         }
     }
-    attacks
+    BitBoard(attacks)
 }
@@ -0,0 +1,59 @@
+use std::arch::x86_64::_pext_u64;
+
+use crate::{bitboard::BitBoard, square::Square};
+
+/// Entries of a bishop table, one for every subset of every square's relevance mask
+pub const BISHOP_TABLE_SIZE: usize = 5248;
+/// Entries of a rook table, one for every subset of every square's relevance mask
+pub const ROOK_TABLE_SIZE: usize = 102400;
+
+/// Slider attack table indexed with the BMI2 `pext` instruction, which packs the occupancy bits
+/// under a square's relevance mask into a dense index without needing a magic number
+pub struct PextTable<const N: usize> {
+    masks: [BitBoard; 64],
+    offsets: [usize; 64],
+    attacks: [BitBoard; N],
+    len: usize,
+}
+
+impl<const N: usize> PextTable<N> {
+    pub const fn empty() -> PextTable<N> {
+        PextTable {
+            masks: [BitBoard::empty(); 64],
+            offsets: [0; 64],
+            attacks: [BitBoard::empty(); N],
+            len: 0,
+        }
+    }
+
+    /// Starts the entries of `square`, which must be followed by a `push` for every subset of
+    /// `mask` in increasing order, as that is the order pext packs them in. Building the table
+    /// does not need BMI2, so it is filled at compile time along with the magic tables.
+    pub const fn start_square(&mut self, square: Square, mask: BitBoard) {
+        self.masks[square as usize] = mask;
+        self.offsets[square as usize] = self.len;
+    }
+
+    pub const fn push(&mut self, attacks: BitBoard) {
+        self.attacks[self.len] = attacks;
+        self.len += 1;
+    }
+
+    /// # Safety
+    ///
+    /// The CPU must support BMI2
+    #[inline]
+    #[target_feature(enable = "bmi2")]
+    pub unsafe fn get(&self, square: Square, occupancy: BitBoard) -> BitBoard {
+        let index = _pext_u64(occupancy.0, self.masks[square as usize].0) as usize;
+        self.attacks[self.offsets[square as usize] + index]
+    }
+}
+
+/// Returns true if the running CPU has the `pext` instruction. Builds for a target with BMI2
+/// enabled, such as with `-C target-cpu=native`, know this at compile time and skip the check,
+/// while other builds read the feature flags std detects once per process.
+#[inline]
+pub fn has_bmi2() -> bool {
+    cfg!(target_feature = "bmi2") || is_x86_feature_detected!("bmi2")
+}
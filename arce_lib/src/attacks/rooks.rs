@@ -1,48 +1,48 @@
 use crate::{bitboard::BitBoard, square::Square};
 
-pub fn mask_rook_attacks(square: Square) -> BitBoard {
-    let mut attacks = BitBoard::empty();
+pub const fn mask_rook_attacks(square: Square) -> BitBoard {
+    let mut attacks = 0;
 
-    let (tf, tr): (i8, i8) = square.into();
+    let (tf, tr) = ((square as u8 & 0b111) as i8, (square as u8 / 8) as i8);
 
     let mut r = tr + 1;
     while r <= 6 {
-        attacks |= BitBoard(1) << (r * 8 + tf) as u8;
+        attacks |= 1 << (r * 8 + tf);
         r += 1;
     }
 
     r = tr - 1;
     while r >= 1 {
-        attacks |= BitBoard(1) << (r * 8 + tf) as u8;
+        attacks |= 1 << (r * 8 + tf);
         r -= 1;
     }
 
     let mut f = tf + 1;
     while f <= 6 {
-        attacks |= BitBoard(1) << (tr * 8 + f) as u8;
+        attacks |= 1 << (tr * 8 + f);
         f += 1;
     }
 
     f = tf - 1;
     while f >= 1 {
-        attacks |= BitBoard(1) << (tr * 8 + f) as u8;
+        attacks |= 1 << (tr * 8 + f);
         f -= 1;
     }
 
-    attacks
+    BitBoard(attacks)
 }
 
-pub fn gen_rook_attacks(square: Square, block: BitBoard) -> BitBoard {
-    let mut attacks = BitBoard::empty();
+pub const fn gen_rook_attacks(square: Square, block: BitBoard) -> BitBoard {
+    let mut attacks = 0;
 
-    let (tf, tr): (i8, i8) = square.into();
+    let (tf, tr) = ((square as u8 & 0b111) as i8, (square as u8 / 8) as i8);
 
     let mut r = tr + 1;
     while r <= 7 {
-        let cb = BitBoard(1) << (r * 8 + tf) as u8;
+        let cb = 1 << (r * 8 + tf);
         attacks |= cb;
 
-        if (cb & block).is_not_empty() {
+        if cb & block.0 != 0 {
             break;
         }
 
@@ -51,10 +51,10 @@ pub fn gen_rook_attacks(square: Square, block: BitBoard) -> BitBoard {
 
     r = tr - 1;
     while r >= 0 {
-        let cb = BitBoard(1) << (r * 8 + tf) as u8;
+        let cb = 1 << (r * 8 + tf);
         attacks |= cb;
 
-        if (cb & block).is_not_empty() {
+        if cb & block.0 != 0 {
             break;
         }
 
@@ -63,10 +63,10 @@ pub fn gen_rook_attacks(square: Square, block: BitBoard) -> BitBoard {
 
     let mut f = tf + 1;
     while f <= 7 {
-        let cb = BitBoard(1) << (tr * 8 + f) as u8;
+        let cb = 1 << (tr * 8 + f);
         attacks |= cb;
 
-        if (cb & block).is_not_empty() {
+        if cb & block.0 != 0 {
             break;
         }
 
@@ -75,15 +75,15 @@ pub fn gen_rook_attacks(square: Square, block: BitBoard) -> BitBoard {
 
     f = tf - 1;
     while f >= 0 {
-        let cb = BitBoard(1) << (tr * 8 + f) as u8;
+        let cb = 1 << (tr * 8 + f);
         attacks |= cb;
 
-        if (cb & block).is_not_empty() {
+        if cb & block.0 != 0 {
             break;
         }
 
         f -= 1;
     }
 
-    attacks
+    BitBoard(attacks)
 }
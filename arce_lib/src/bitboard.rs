@@ -1,5 +1,5 @@
 use std::{
-    convert::{TryFrom, TryInto},
+    convert::TryFrom,
     fmt::Display,
     ops::{
         BitAnd, BitAndAssign, BitOr, BitOrAssign, BitXor, BitXorAssign, Mul, MulAssign, Not, Shl,
@@ -7,65 +7,176 @@ use std::{
     },
 };
 
-use crate::square::Square;
+use crate::{side::Side, square::Square};
+
+#[allow(clippy::unreadable_literal)]
+pub const FILE_A: BitBoard = BitBoard(0x0101010101010101);
+#[allow(clippy::unreadable_literal)]
+pub const FILE_H: BitBoard = BitBoard(0x8080808080808080);
 
 #[derive(Clone, Copy, Debug)]
 pub struct BitBoard(pub u64);
 
 impl BitBoard {
     #[inline]
-    pub fn set_bit(&mut self, square: Square) {
-        *self |= BitBoard::from(square);
+    pub const fn set_bit(&mut self, square: Square) {
+        self.0 |= 1 << square as u8;
     }
 
     #[inline]
-    pub fn get_bit(self, square: Square) -> BitBoard {
-        self & square.into()
+    pub const fn get_bit(self, square: Square) -> BitBoard {
+        BitBoard(self.0 & 1 << square as u8)
     }
 
     #[inline]
-    pub fn pop_bit(&mut self, square: Square) {
-        *self &= !BitBoard::from(square);
+    pub const fn pop_bit(&mut self, square: Square) {
+        self.0 &= !(1 << square as u8);
     }
 
     #[inline]
-    pub fn count_bits(mut self) -> u8 {
-        let mut count = 0;
-        while !self.is_empty() {
-            count += 1;
-            self &= BitBoard(self.0 - 1);
-        }
-        count
+    pub const fn count_bits(self) -> u8 {
+        self.popcount() as u8
+    }
+
+    /// Number of set squares, using the popcnt instruction where the target has it
+    #[inline]
+    pub const fn popcount(self) -> u32 {
+        self.0.count_ones()
+    }
+
+    /// Returns true if at least two squares are set
+    #[inline]
+    pub const fn more_than_one(self) -> bool {
+        self.0 & self.0.wrapping_sub(1) != 0
     }
 
     #[inline]
     pub fn get_lsb_square(self) -> Option<Square> {
         if self.is_not_empty() {
-            Some(
-                BitBoard((self.0 & (self.0 as i64).wrapping_neg() as u64) - 1)
-                    .count_bits()
-                    .try_into()
-                    .unwrap(),
-            )
+            Some(Square::from_u8_unchecked(self.0.trailing_zeros() as u8))
         } else {
             None
         }
     }
 
+    /// Removes and returns the lowest set square
+    #[inline]
+    pub fn pop_lsb(&mut self) -> Option<Square> {
+        let square = self.get_lsb_square()?;
+        self.0 &= self.0 - 1;
+        Some(square)
+    }
+
+    // Shifts by one square. Squares shifted off the board, or across the edge onto the
+    // opposite file, are dropped.
+
+    #[inline]
+    pub const fn shift_north(self) -> BitBoard {
+        BitBoard(self.0 << 8)
+    }
+
+    #[inline]
+    pub const fn shift_south(self) -> BitBoard {
+        BitBoard(self.0 >> 8)
+    }
+
+    #[inline]
+    pub const fn shift_east(self) -> BitBoard {
+        BitBoard((self.0 << 1) & !FILE_A.0)
+    }
+
+    #[inline]
+    pub const fn shift_west(self) -> BitBoard {
+        BitBoard((self.0 >> 1) & !FILE_H.0)
+    }
+
+    #[inline]
+    pub const fn shift_north_east(self) -> BitBoard {
+        BitBoard((self.0 << 9) & !FILE_A.0)
+    }
+
+    #[inline]
+    pub const fn shift_north_west(self) -> BitBoard {
+        BitBoard((self.0 << 7) & !FILE_H.0)
+    }
+
+    #[inline]
+    pub const fn shift_south_east(self) -> BitBoard {
+        BitBoard((self.0 >> 7) & !FILE_A.0)
+    }
+
+    #[inline]
+    pub const fn shift_south_west(self) -> BitBoard {
+        BitBoard((self.0 >> 9) & !FILE_H.0)
+    }
+
+    /// Shifts one square towards the side's promotion rank, as a pawn push
+    #[inline]
+    pub const fn shift_forward(self, side: Side) -> BitBoard {
+        match side {
+            Side::White => self.shift_north(),
+            Side::Black => self.shift_south(),
+        }
+    }
+
+    /// Every set square along with all squares north of it
+    #[inline]
+    pub const fn north_fill(self) -> BitBoard {
+        let mut b = self.0;
+        b |= b << 8;
+        b |= b << 16;
+        b |= b << 32;
+        BitBoard(b)
+    }
+
+    /// Every set square along with all squares south of it
+    #[inline]
+    pub const fn south_fill(self) -> BitBoard {
+        let mut b = self.0;
+        b |= b >> 8;
+        b |= b >> 16;
+        b |= b >> 32;
+        BitBoard(b)
+    }
+
+    /// The whole of every file with a set square
+    #[inline]
+    pub const fn file_fill(self) -> BitBoard {
+        BitBoard(self.north_fill().0 | self.south_fill().0)
+    }
+
+    /// Squares in front of the set squares from the side's point of view, not including them
+    #[inline]
+    pub const fn front_span(self, side: Side) -> BitBoard {
+        match side {
+            Side::White => self.north_fill().shift_north(),
+            Side::Black => self.south_fill().shift_south(),
+        }
+    }
+
+    /// Squares behind the set squares from the side's point of view, not including them
+    #[inline]
+    pub const fn rear_span(self, side: Side) -> BitBoard {
+        match side {
+            Side::White => self.south_fill().shift_south(),
+            Side::Black => self.north_fill().shift_north(),
+        }
+    }
+
     /// Return the occupancy of an attack mask
     #[inline]
-    pub fn set_occupancy(mut self, index: u32, bits_in_mask: u8) -> BitBoard {
-        let mut occupancy = BitBoard::empty();
+    pub const fn set_occupancy(mut self, index: u32, bits_in_mask: u8) -> BitBoard {
+        let mut occupancy = 0;
         let mut count = 0;
         while count < bits_in_mask {
-            let square = self.get_lsb_square().unwrap();
-            self.pop_bit(square);
+            let lsb = self.0 & self.0.wrapping_neg();
+            self.0 ^= lsb;
             if (index & (1 << count)) != 0 {
-                occupancy |= BitBoard::from(square);
+                occupancy |= lsb;
             }
             count += 1;
         }
-        occupancy
+        BitBoard(occupancy)
     }
 
     #[inline]
@@ -84,6 +195,38 @@ impl BitBoard {
     }
 }
 
+/// Iterates the set squares from a1 to h8
+impl IntoIterator for BitBoard {
+    type Item = Square;
+    type IntoIter = Squares;
+
+    #[inline]
+    fn into_iter(self) -> Self::IntoIter {
+        Squares(self)
+    }
+}
+
+/// Iterator over the set squares of a `BitBoard`, lowest first
+#[derive(Clone, Copy, Debug)]
+pub struct Squares(BitBoard);
+
+impl Iterator for Squares {
+    type Item = Square;
+
+    #[inline]
+    fn next(&mut self) -> Option<Square> {
+        self.0.pop_lsb()
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.0.popcount() as usize;
+        (len, Some(len))
+    }
+}
+
+impl ExactSizeIterator for Squares {}
+
 impl Mul for BitBoard {
     type Output = BitBoard;
 
@@ -248,3 +391,95 @@ impl Display for BitBoard {
         write!(f, "{}", bstr)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        side::Side,
+        square::{Square, SQUARES},
+    };
+
+    use super::{BitBoard, FILE_A, FILE_H};
+
+    fn board(squares: &[Square]) -> BitBoard {
+        let mut bitboard = BitBoard::empty();
+        for &square in squares {
+            bitboard.set_bit(square);
+        }
+        bitboard
+    }
+
+    #[test]
+    fn test_iteration() {
+        let squares = [Square::A1, Square::E4, Square::D5, Square::H8];
+        let bitboard = board(&squares);
+        let iter = bitboard.into_iter();
+        assert_eq!(iter.len(), 4);
+        let collected: Vec<Square> = iter.collect();
+        assert_eq!(
+            collected,
+            vec![Square::A1, Square::E4, Square::D5, Square::H8]
+        );
+        assert_eq!(BitBoard::empty().into_iter().next(), None);
+        assert_eq!(BitBoard(!0).into_iter().count(), 64);
+
+        assert_eq!(bitboard.popcount(), 4);
+        assert!(bitboard.more_than_one());
+        assert!(!board(&[Square::C3]).more_than_one());
+        assert!(!BitBoard::empty().more_than_one());
+    }
+
+    #[test]
+    fn test_shifts() {
+        // Shifting each square matches stepping its file and rank, dropping squares that leave
+        // the board
+        for &square in SQUARES.iter() {
+            let (file, rank): (i8, i8) = square.into();
+            let bitboard = BitBoard::from(square);
+            let steps = [
+                (bitboard.shift_north(), 0, 1),
+                (bitboard.shift_south(), 0, -1),
+                (bitboard.shift_east(), 1, 0),
+                (bitboard.shift_west(), -1, 0),
+                (bitboard.shift_north_east(), 1, 1),
+                (bitboard.shift_north_west(), -1, 1),
+                (bitboard.shift_south_east(), 1, -1),
+                (bitboard.shift_south_west(), -1, -1),
+            ];
+            for &(shifted, df, dr) in steps.iter() {
+                let (f, r) = (file + df, rank + dr);
+                let expected = if (0..8).contains(&f) && (0..8).contains(&r) {
+                    BitBoard::from(Square::from_fr_unchecked(f as u8, r as u8))
+                } else {
+                    BitBoard::empty()
+                };
+                assert_eq!(shifted, expected, "{} by ({}, {})", square, df, dr);
+            }
+        }
+        assert_eq!(FILE_A.shift_west(), BitBoard::empty());
+        assert_eq!(FILE_H.shift_east(), BitBoard::empty());
+        assert_eq!(
+            board(&[Square::E2]).shift_forward(Side::White),
+            board(&[Square::E3])
+        );
+        assert_eq!(
+            board(&[Square::E7]).shift_forward(Side::Black),
+            board(&[Square::E6])
+        );
+    }
+
+    #[test]
+    fn test_fills() {
+        let pawns = board(&[Square::C3, Square::F6]);
+        assert_eq!(pawns.file_fill().popcount(), 16);
+        assert_eq!(pawns.north_fill().popcount(), 6 + 3);
+        assert_eq!(pawns.south_fill().popcount(), 3 + 6);
+        assert_eq!(pawns.front_span(Side::White).popcount(), 5 + 2);
+        assert_eq!(pawns.front_span(Side::Black).popcount(), 2 + 5);
+        assert_eq!(pawns.front_span(Side::White), pawns.rear_span(Side::Black));
+        assert_eq!(
+            pawns.front_span(Side::White) | pawns.rear_span(Side::White) | pawns,
+            pawns.file_fill()
+        );
+    }
+}
@@ -1,70 +1,163 @@
 use std::fmt::Display;
 
-use crate::square::Square;
-
-const SQUARE_CASTLE_RIGHTS: [u8; 64] = [
-    0b1101, 0b1111, 0b1111, 0b1111, 0b1100, 0b1111, 0b1111, 0b1110, 0b1111, 0b1111, 0b1111, 0b1111,
-    0b1111, 0b1111, 0b1111, 0b1111, 0b1111, 0b1111, 0b1111, 0b1111, 0b1111, 0b1111, 0b1111, 0b1111,
-    0b1111, 0b1111, 0b1111, 0b1111, 0b1111, 0b1111, 0b1111, 0b1111, 0b1111, 0b1111, 0b1111, 0b1111,
-    0b1111, 0b1111, 0b1111, 0b1111, 0b1111, 0b1111, 0b1111, 0b1111, 0b1111, 0b1111, 0b1111, 0b1111,
-    0b1111, 0b1111, 0b1111, 0b1111, 0b1111, 0b1111, 0b1111, 0b1111, 0b0111, 0b1111, 0b1111, 0b1111,
-    0b0011, 0b1111, 0b1111, 0b1011,
-];
-#[derive(Clone, Copy, Debug)]
-pub struct CastleRights(u8);
+use crate::{side::Side, square::Square};
+
+const WK: u8 = 0b0001;
+const WQ: u8 = 0b0010;
+const BK: u8 = 0b0100;
+const BQ: u8 = 0b1000;
+
+/// Castling rights together with the starting squares of the kings and castling rooks, so that
+/// Chess960 positions with any king and rook files can be played.
+///
+/// The rook squares are indexed in the same order as the rights bits: white kingside, white
+/// queenside, black kingside and black queenside.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CastleRights {
+    rights: u8,
+    kings: [Square; 2],
+    rooks: [Square; 4],
+}
 
 impl CastleRights {
+    /// No castling rights, with the kings and rooks on their standard squares
+    pub const fn none() -> CastleRights {
+        CastleRights {
+            rights: 0,
+            kings: [Square::E1, Square::E8],
+            rooks: [Square::H1, Square::A1, Square::H8, Square::A8],
+        }
+    }
+
     #[inline]
     pub const fn wk(&self) -> bool {
-        self.0 & 0b0001 != 0
+        self.rights & WK != 0
     }
 
     #[inline]
     pub const fn wq(&self) -> bool {
-        self.0 & 0b0010 != 0
+        self.rights & WQ != 0
     }
 
     #[inline]
     pub const fn bk(&self) -> bool {
-        self.0 & 0b0100 != 0
+        self.rights & BK != 0
     }
 
     #[inline]
     pub const fn bq(&self) -> bool {
-        self.0 & 0b1000 != 0
+        self.rights & BQ != 0
     }
 
+    /// Returns true if `side` may still castle on the given wing
     #[inline]
-    pub fn update(&mut self, square: Square) {
-        self.0 &= SQUARE_CASTLE_RIGHTS[square as usize];
+    pub const fn has(&self, side: Side, kingside: bool) -> bool {
+        self.rights & (1 << Self::index(side, kingside)) != 0
     }
-}
 
-impl From<&str> for CastleRights {
-    fn from(value: &str) -> Self {
-        let mut rights = 0;
-        if value.contains('K') {
-            rights |= 0b1000;
-        }
-        if value.contains('Q') {
-            rights |= 0b0100;
+    /// Raw rights bits, used to index per-rights tables such as Zobrist keys
+    #[inline]
+    pub const fn bits(&self) -> u8 {
+        self.rights
+    }
+
+    /// Starting square of the king of `side`
+    #[inline]
+    pub const fn king_square(&self, side: Side) -> Square {
+        self.kings[side as usize]
+    }
+
+    /// Starting square of the rook `side` castles with on the given wing
+    #[inline]
+    pub const fn rook_square(&self, side: Side, kingside: bool) -> Square {
+        self.rooks[Self::index(side, kingside)]
+    }
+
+    /// Grants the right to castle with the king on `king` and the rook on `rook`
+    pub fn add(&mut self, side: Side, kingside: bool, king: Square, rook: Square) {
+        let index = Self::index(side, kingside);
+        self.rights |= 1 << index;
+        self.kings[side as usize] = king;
+        self.rooks[index] = rook;
+    }
+
+    /// Removes the rights lost by a move from or to `square`
+    #[inline]
+    pub fn update(&mut self, square: Square) {
+        if square == self.kings[0] {
+            self.rights &= !(WK | WQ);
         }
-        if value.contains('k') {
-            rights |= 0b0010;
+        if square == self.kings[1] {
+            self.rights &= !(BK | BQ);
         }
-        if value.contains('q') {
-            rights |= 0b0001;
+        for (i, rook) in self.rooks.iter().enumerate() {
+            if square == *rook {
+                self.rights &= !(1 << i);
+            }
         }
-        CastleRights(rights)
+    }
+
+    #[inline]
+    const fn index(side: Side, kingside: bool) -> usize {
+        2 * side as usize + !kingside as usize
     }
 }
 
+impl Default for CastleRights {
+    fn default() -> Self {
+        CastleRights::none()
+    }
+}
+
+/// Formats the rights as a FEN castling field. When every castling rook is in its corner the
+/// rights are written as `KQkq`, and otherwise every rook is written by its file as in
+/// Shredder-FEN, so that the field never mixes the two notations.
 impl Display for CastleRights {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let wk = if self.wk() { 'K' } else { '-' };
-        let wq = if self.wq() { 'Q' } else { '-' };
-        let bk = if self.bk() { 'k' } else { '-' };
-        let bq = if self.bq() { 'q' } else { '-' };
-        write!(f, "{}{}{}{}", wk, wq, bk, bq)
+        if self.rights == 0 {
+            return write!(f, "-");
+        }
+        const RIGHTS: [(u8, char); 4] = [(7, 'K'), (0, 'Q'), (7, 'k'), (0, 'q')];
+        let granted = || (0..4).filter(|i| self.rights & (1 << i) != 0);
+        let corners = granted().all(|i| self.rooks[i] as u8 & 0b111 == RIGHTS[i].0);
+        for i in granted() {
+            let (_, letter) = RIGHTS[i];
+            let file = self.rooks[i] as u8 & 0b111;
+            if corners {
+                write!(f, "{}", letter)?;
+            } else if letter.is_ascii_uppercase() {
+                write!(f, "{}", (b'A' + file) as char)?;
+            } else {
+                write!(f, "{}", (b'a' + file) as char)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::utils::fen::parse_fen;
+
+    #[test]
+    fn test_fen_castle_rights() {
+        // Each letter sets only its own right
+        for (field, rights) in [
+            ("K", [true, false, false, false]),
+            ("Q", [false, true, false, false]),
+            ("k", [false, false, true, false]),
+            ("q", [false, false, false, true]),
+            ("KQkq", [true, true, true, true]),
+            ("-", [false, false, false, false]),
+        ] {
+            let fen = format!("r3k2r/8/8/8/8/8/8/R3K2R w {} - 0 1", field);
+            let castle = parse_fen(&fen).unwrap().castle;
+            assert_eq!(
+                [castle.wk(), castle.wq(), castle.bk(), castle.bq()],
+                rights,
+                "{}",
+                field
+            );
+        }
     }
 }
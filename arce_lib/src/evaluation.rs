@@ -96,17 +96,9 @@ pub fn evaluate(position: &Position) -> i32 {
     // Evaluation score
     let mut score = 0;
 
-    let mut bitboard;
-    let mut piece;
-    let mut square;
-
     for p in PIECES {
-        bitboard = position.get_piece_bitboard(p);
-
-        while let Some(s) = bitboard.get_lsb_square() {
-            piece = p;
-            square = s;
-            score += MATERIAL_SCORE[piece as usize];
+        for square in position.get_piece_bitboard(p) {
+            score += MATERIAL_SCORE[p as usize];
 
             match p {
                 crate::piece::Piece::WPawn => score += PAWN_SCORE[square as usize],
@@ -131,7 +123,6 @@ pub fn evaluate(position: &Position) -> i32 {
                 }
                 _ => (),
             }
-            bitboard.pop_bit(square);
         }
     }
     if position.side == Side::White {
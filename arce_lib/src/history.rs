@@ -0,0 +1,128 @@
+use crate::position::Position;
+
+/// Zobrist hashes of the positions leading up to the current one, used to detect repetitions.
+///
+/// The hash of a position is pushed before a move is made from it and popped when the move is
+/// unmade. Positions from before the last irreversible move (pawn move or capture) can never be
+/// repeated, so only the last `halfmove_clock` entries are ever looked at.
+#[derive(Clone, Debug, Default)]
+pub struct GameHistory {
+    hashes: Vec<u64>,
+}
+
+impl GameHistory {
+    pub fn new() -> GameHistory {
+        GameHistory {
+            hashes: Vec::with_capacity(256),
+        }
+    }
+
+    #[inline]
+    pub fn push(&mut self, hash: u64) {
+        self.hashes.push(hash);
+    }
+
+    #[inline]
+    pub fn pop(&mut self) -> Option<u64> {
+        self.hashes.pop()
+    }
+
+    pub fn clear(&mut self) {
+        self.hashes.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.hashes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hashes.is_empty()
+    }
+
+    /// Returns true if the position has occurred at least once before
+    #[inline]
+    pub fn is_repetition(&self, pos: &Position) -> bool {
+        self.count_repetitions(pos, 1) >= 1
+    }
+
+    /// Returns true if the position has occurred at least twice before, so that this is its
+    /// third occurrence
+    #[inline]
+    pub fn is_threefold(&self, pos: &Position) -> bool {
+        self.count_repetitions(pos, 2) >= 2
+    }
+
+    /// Counts earlier occurrences of the position, stopping once `limit` is reached
+    #[inline]
+    fn count_repetitions(&self, pos: &Position, limit: u32) -> u32 {
+        let len = self.hashes.len();
+        let reversible = (pos.halfmove_clock as usize).min(len);
+        let mut count = 0;
+        // Only positions with the same side to move can be equal
+        let mut distance = 2;
+        while distance <= reversible {
+            if self.hashes[len - distance] == pos.hash {
+                count += 1;
+                if count >= limit {
+                    break;
+                }
+            }
+            distance += 2;
+        }
+        count
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        attacks::{Attacks, ATTACKS},
+        move_gen::generate_moves,
+        position::Position,
+        utils::fen::{parse_fen, START_POSITION},
+    };
+
+    use super::GameHistory;
+
+    fn play(pos: &mut Position, history: &mut GameHistory, attacks: &Attacks, mov: &str) {
+        let moves = generate_moves(attacks, pos);
+        let mov = moves.into_iter().find(|m| m.to_string() == mov).unwrap();
+        history.push(pos.hash);
+        assert!(pos.make_move(mov, attacks));
+    }
+
+    #[test]
+    fn test_repetition() {
+        let mut pos = parse_fen(START_POSITION).unwrap();
+        let mut history = GameHistory::new();
+
+        for mov in ["g1f3", "g8f6", "f3g1"] {
+            play(&mut pos, &mut history, &ATTACKS, mov);
+            assert!(!history.is_repetition(&pos));
+        }
+        play(&mut pos, &mut history, &ATTACKS, "f6g8");
+        assert!(history.is_repetition(&pos));
+        assert!(!history.is_threefold(&pos));
+
+        for mov in ["g1f3", "g8f6", "f3g1", "f6g8"] {
+            play(&mut pos, &mut history, &ATTACKS, mov);
+        }
+        assert!(history.is_threefold(&pos));
+    }
+
+    #[test]
+    fn test_irreversible_move() {
+        let mut pos = parse_fen(START_POSITION).unwrap();
+        let mut history = GameHistory::new();
+
+        for mov in ["g1f3", "g8f6", "f3g1", "f6g8", "e2e3", "e7e6"] {
+            play(&mut pos, &mut history, &ATTACKS, mov);
+        }
+        // Only the positions since the pawn moves can be repeated
+        for mov in ["g1f3", "g8f6", "f3g1", "f6g8"] {
+            play(&mut pos, &mut history, &ATTACKS, mov);
+        }
+        assert!(history.is_repetition(&pos));
+        assert!(!history.is_threefold(&pos));
+    }
+}
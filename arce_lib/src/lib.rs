@@ -2,12 +2,17 @@ pub mod attacks;
 pub mod bitboard;
 pub mod castle_rights;
 pub mod evaluation;
+pub mod history;
 pub mod move_gen;
 pub mod move_list;
+pub mod move_picker;
 pub mod perft;
 pub mod piece;
 pub mod position;
 pub mod search;
+pub mod see;
 pub mod side;
 pub mod square;
+pub mod transposition;
 pub mod utils;
+pub mod zobrist;
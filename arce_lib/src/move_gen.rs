@@ -7,9 +7,17 @@ use crate::{
     square::Square,
 };
 
+pub use self::legal::{
+    generate_captures, generate_evasions, generate_legal_moves, generate_quiets,
+};
+
+mod legal;
+mod legality;
+
+/// Generates pseudo legal moves, some of which may leave the king in check
 #[inline]
 pub fn generate_moves(attacks: &Attacks, pos: &Position) -> MoveList {
-    let mut moves = MoveList::with_capacity(256);
+    let mut moves = MoveList::new();
     match pos.side {
         Side::White => gen_w_moves(attacks, pos, &mut moves),
         Side::Black => gen_b_moves(attacks, pos, &mut moves),
@@ -37,8 +45,7 @@ fn gen_w_moves(attacks: &Attacks, pos: &Position, moves: &mut MoveList) {
 }
 
 fn gen_w_pawn_moves(attacks: &Attacks, pos: &Position, moves: &mut MoveList) {
-    let mut bitboard = pos.get_piece_bitboard(Piece::WPawn);
-    while let Some(source_square) = bitboard.get_lsb_square() {
+    for source_square in pos.get_piece_bitboard(Piece::WPawn) {
         // Generate quiet pawn moves
         if let Some(target_square) = Square::from_u8(source_square as u8 + 8) {
             if pos.all_occupancies.get_bit(target_square).is_empty() {
@@ -119,9 +126,9 @@ fn gen_w_pawn_moves(attacks: &Attacks, pos: &Position, moves: &mut MoveList) {
             }
         }
         // Init pawn attacks_bb
-        let mut attacks_bb = attacks.get_w_pawn_attacks(source_square) & pos.b_occupancies;
+        let attacks_bb = attacks.get_w_pawn_attacks(source_square) & pos.b_occupancies;
         // Generate pawn captures
-        while let Some(target_square) = attacks_bb.get_lsb_square() {
+        for target_square in attacks_bb {
             if source_square >= Square::A7 && source_square <= Square::H7 {
                 // Pawn capture promotion
                 moves.push(Move::encode(
@@ -177,7 +184,6 @@ fn gen_w_pawn_moves(attacks: &Attacks, pos: &Position, moves: &mut MoveList) {
                     false,
                 ));
             }
-            attacks_bb.pop_bit(target_square);
         }
         // Generate en passant captures
         if let Some(en_passant_square) = pos.en_passant {
@@ -196,16 +202,12 @@ fn gen_w_pawn_moves(attacks: &Attacks, pos: &Position, moves: &mut MoveList) {
                 ));
             }
         }
-
-        bitboard.pop_bit(source_square);
     }
 }
 
 fn gen_w_knight_moves(attacks: &Attacks, pos: &Position, moves: &mut MoveList) {
-    let mut bitboard = pos.get_piece_bitboard(Piece::WKnight);
-    while let Some(source_square) = bitboard.get_lsb_square() {
-        let mut attacks_bb = attacks.get_knight_attacks(source_square) & !pos.w_occupancies;
-        while let Some(target_square) = attacks_bb.get_lsb_square() {
+    for source_square in pos.get_piece_bitboard(Piece::WKnight) {
+        for target_square in attacks.get_knight_attacks(source_square) & !pos.w_occupancies {
             if pos.b_occupancies.get_bit(target_square).is_empty() {
                 // Quiet move
                 moves.push(Move::encode(
@@ -231,19 +233,15 @@ fn gen_w_knight_moves(attacks: &Attacks, pos: &Position, moves: &mut MoveList) {
                     false,
                 ));
             }
-
-            attacks_bb.pop_bit(target_square);
         }
-        bitboard.pop_bit(source_square);
     }
 }
 
 fn gen_w_bishop_moves(attacks: &Attacks, pos: &Position, moves: &mut MoveList) {
-    let mut bitboard = pos.get_piece_bitboard(Piece::WBishop);
-    while let Some(source_square) = bitboard.get_lsb_square() {
-        let mut attacks_bb =
+    for source_square in pos.get_piece_bitboard(Piece::WBishop) {
+        let attacks_bb =
             attacks.get_bishop_attacks(source_square, pos.all_occupancies) & !pos.w_occupancies;
-        while let Some(target_square) = attacks_bb.get_lsb_square() {
+        for target_square in attacks_bb {
             if pos.b_occupancies.get_bit(target_square).is_empty() {
                 // Quiet move
                 moves.push(Move::encode(
@@ -269,19 +267,15 @@ fn gen_w_bishop_moves(attacks: &Attacks, pos: &Position, moves: &mut MoveList) {
                     false,
                 ));
             }
-
-            attacks_bb.pop_bit(target_square);
         }
-        bitboard.pop_bit(source_square);
     }
 }
 
 fn gen_w_rook_moves(attacks: &Attacks, pos: &Position, moves: &mut MoveList) {
-    let mut bitboard = pos.get_piece_bitboard(Piece::WRook);
-    while let Some(source_square) = bitboard.get_lsb_square() {
-        let mut attacks_bb =
+    for source_square in pos.get_piece_bitboard(Piece::WRook) {
+        let attacks_bb =
             attacks.get_rook_attacks(source_square, pos.all_occupancies) & !pos.w_occupancies;
-        while let Some(target_square) = attacks_bb.get_lsb_square() {
+        for target_square in attacks_bb {
             if pos.b_occupancies.get_bit(target_square).is_empty() {
                 // Quiet move
                 moves.push(Move::encode(
@@ -307,19 +301,15 @@ fn gen_w_rook_moves(attacks: &Attacks, pos: &Position, moves: &mut MoveList) {
                     false,
                 ));
             }
-
-            attacks_bb.pop_bit(target_square);
         }
-        bitboard.pop_bit(source_square);
     }
 }
 
 fn gen_w_queen_moves(attacks: &Attacks, pos: &Position, moves: &mut MoveList) {
-    let mut bitboard = pos.get_piece_bitboard(Piece::WQueen);
-    while let Some(source_square) = bitboard.get_lsb_square() {
-        let mut attacks_bb =
+    for source_square in pos.get_piece_bitboard(Piece::WQueen) {
+        let attacks_bb =
             attacks.get_queen_attacks(source_square, pos.all_occupancies) & !pos.w_occupancies;
-        while let Some(target_square) = attacks_bb.get_lsb_square() {
+        for target_square in attacks_bb {
             if pos.b_occupancies.get_bit(target_square).is_empty() {
                 // Quiet move
                 moves.push(Move::encode(
@@ -345,18 +335,13 @@ fn gen_w_queen_moves(attacks: &Attacks, pos: &Position, moves: &mut MoveList) {
                     false,
                 ));
             }
-
-            attacks_bb.pop_bit(target_square);
         }
-        bitboard.pop_bit(source_square);
     }
 }
 
 fn gen_w_king_moves(attacks: &Attacks, pos: &Position, moves: &mut MoveList) {
-    let mut bitboard = pos.get_piece_bitboard(Piece::WKing);
-    while let Some(source_square) = bitboard.get_lsb_square() {
-        let mut attacks_bb = attacks.get_king_attacks(source_square) & !pos.w_occupancies;
-        while let Some(target_square) = attacks_bb.get_lsb_square() {
+    for source_square in pos.get_piece_bitboard(Piece::WKing) {
+        for target_square in attacks.get_king_attacks(source_square) & !pos.w_occupancies {
             if pos.b_occupancies.get_bit(target_square).is_empty() {
                 // Quiet move
                 moves.push(Move::encode(
@@ -382,60 +367,9 @@ fn gen_w_king_moves(attacks: &Attacks, pos: &Position, moves: &mut MoveList) {
                     false,
                 ));
             }
-
-            attacks_bb.pop_bit(target_square);
-        }
-        bitboard.pop_bit(source_square);
-    }
-    // Kingside castling possible
-    if pos.castle.wk() {
-        // Make sure squares between king and king's rook are empty
-        if pos.all_occupancies.get_bit(Square::F1).is_empty()
-            && pos.all_occupancies.get_bit(Square::G1).is_empty()
-        {
-            // Make sure king and f1 are not attacked
-            if !attacks.is_square_attacked(pos, Square::E1, Side::Black)
-                && !attacks.is_square_attacked(pos, Square::F1, Side::Black)
-            {
-                // Kingside castle
-                moves.push(Move::encode(
-                    Square::E1,
-                    Square::G1,
-                    Piece::WKing,
-                    Piece::None,
-                    false,
-                    false,
-                    false,
-                    true,
-                ));
-            }
-        }
-    }
-    // Queenside castling possible
-    if pos.castle.wq() {
-        // Make sure squares between king and queen's rook are empty
-        if pos.all_occupancies.get_bit(Square::B1).is_empty()
-            && pos.all_occupancies.get_bit(Square::C1).is_empty()
-            && pos.all_occupancies.get_bit(Square::D1).is_empty()
-        {
-            // Make sure king and d1 are not attacked
-            if !attacks.is_square_attacked(pos, Square::E1, Side::Black)
-                && !attacks.is_square_attacked(pos, Square::D1, Side::Black)
-            {
-                // Queenside castle
-                moves.push(Move::encode(
-                    Square::E1,
-                    Square::C1,
-                    Piece::WKing,
-                    Piece::None,
-                    false,
-                    false,
-                    false,
-                    true,
-                ));
-            }
         }
     }
+    gen_castling_moves(attacks, pos, Side::White, moves);
 }
 
 fn gen_b_moves(attacks: &Attacks, pos: &Position, moves: &mut MoveList) {
@@ -457,8 +391,7 @@ fn gen_b_moves(attacks: &Attacks, pos: &Position, moves: &mut MoveList) {
 }
 
 fn gen_b_pawn_moves(attacks: &Attacks, pos: &Position, moves: &mut MoveList) {
-    let mut bitboard = pos.get_piece_bitboard(Piece::BPawn);
-    while let Some(source_square) = bitboard.get_lsb_square() {
+    for source_square in pos.get_piece_bitboard(Piece::BPawn) {
         // Generate quiet pawn moves
         if let Some(target_square) = Square::from_u8(source_square as u8 - 8) {
             if pos.all_occupancies.get_bit(target_square).is_empty() {
@@ -538,9 +471,9 @@ fn gen_b_pawn_moves(attacks: &Attacks, pos: &Position, moves: &mut MoveList) {
             }
         }
         // Init pawn attacks_bb
-        let mut attacks_bb = attacks.get_b_pawn_attacks(source_square) & pos.w_occupancies;
+        let attacks_bb = attacks.get_b_pawn_attacks(source_square) & pos.w_occupancies;
         // Generate pawn captures
-        while let Some(target_square) = attacks_bb.get_lsb_square() {
+        for target_square in attacks_bb {
             // Pawn capture promotion
             if source_square >= Square::A2 && source_square <= Square::H2 {
                 moves.push(Move::encode(
@@ -596,7 +529,6 @@ fn gen_b_pawn_moves(attacks: &Attacks, pos: &Position, moves: &mut MoveList) {
                     false,
                 ));
             }
-            attacks_bb.pop_bit(target_square);
         }
         // Generate en passant captures
         if let Some(en_passant_square) = pos.en_passant {
@@ -615,16 +547,12 @@ fn gen_b_pawn_moves(attacks: &Attacks, pos: &Position, moves: &mut MoveList) {
                 ));
             }
         }
-
-        bitboard.pop_bit(source_square);
     }
 }
 
 fn gen_b_knight_moves(attacks: &Attacks, pos: &Position, moves: &mut MoveList) {
-    let mut bitboard = pos.get_piece_bitboard(Piece::BKnight);
-    while let Some(source_square) = bitboard.get_lsb_square() {
-        let mut attacks_bb = attacks.get_knight_attacks(source_square) & !pos.b_occupancies;
-        while let Some(target_square) = attacks_bb.get_lsb_square() {
+    for source_square in pos.get_piece_bitboard(Piece::BKnight) {
+        for target_square in attacks.get_knight_attacks(source_square) & !pos.b_occupancies {
             if pos.w_occupancies.get_bit(target_square).is_empty() {
                 // Quiet move
                 moves.push(Move::encode(
@@ -650,19 +578,15 @@ fn gen_b_knight_moves(attacks: &Attacks, pos: &Position, moves: &mut MoveList) {
                     false,
                 ));
             }
-
-            attacks_bb.pop_bit(target_square);
         }
-        bitboard.pop_bit(source_square);
     }
 }
 
 fn gen_b_bishop_moves(attacks: &Attacks, pos: &Position, moves: &mut MoveList) {
-    let mut bitboard = pos.get_piece_bitboard(Piece::BBishop);
-    while let Some(source_square) = bitboard.get_lsb_square() {
-        let mut attacks_bb =
+    for source_square in pos.get_piece_bitboard(Piece::BBishop) {
+        let attacks_bb =
             attacks.get_bishop_attacks(source_square, pos.all_occupancies) & !pos.b_occupancies;
-        while let Some(target_square) = attacks_bb.get_lsb_square() {
+        for target_square in attacks_bb {
             if pos.w_occupancies.get_bit(target_square).is_empty() {
                 // Quiet move
                 moves.push(Move::encode(
@@ -688,19 +612,15 @@ fn gen_b_bishop_moves(attacks: &Attacks, pos: &Position, moves: &mut MoveList) {
                     false,
                 ));
             }
-
-            attacks_bb.pop_bit(target_square);
         }
-        bitboard.pop_bit(source_square);
     }
 }
 
 fn gen_b_rook_moves(attacks: &Attacks, pos: &Position, moves: &mut MoveList) {
-    let mut bitboard = pos.get_piece_bitboard(Piece::BRook);
-    while let Some(source_square) = bitboard.get_lsb_square() {
-        let mut attacks_bb =
+    for source_square in pos.get_piece_bitboard(Piece::BRook) {
+        let attacks_bb =
             attacks.get_rook_attacks(source_square, pos.all_occupancies) & !pos.b_occupancies;
-        while let Some(target_square) = attacks_bb.get_lsb_square() {
+        for target_square in attacks_bb {
             if pos.w_occupancies.get_bit(target_square).is_empty() {
                 // Quiet move
                 moves.push(Move::encode(
@@ -726,19 +646,15 @@ fn gen_b_rook_moves(attacks: &Attacks, pos: &Position, moves: &mut MoveList) {
                     false,
                 ));
             }
-
-            attacks_bb.pop_bit(target_square);
         }
-        bitboard.pop_bit(source_square);
     }
 }
 
 fn gen_b_queen_moves(attacks: &Attacks, pos: &Position, moves: &mut MoveList) {
-    let mut bitboard = pos.get_piece_bitboard(Piece::BQueen);
-    while let Some(source_square) = bitboard.get_lsb_square() {
-        let mut attacks_bb =
+    for source_square in pos.get_piece_bitboard(Piece::BQueen) {
+        let attacks_bb =
             attacks.get_queen_attacks(source_square, pos.all_occupancies) & !pos.b_occupancies;
-        while let Some(target_square) = attacks_bb.get_lsb_square() {
+        for target_square in attacks_bb {
             if pos.w_occupancies.get_bit(target_square).is_empty() {
                 // Quiet move
                 moves.push(Move::encode(
@@ -763,18 +679,13 @@ fn gen_b_queen_moves(attacks: &Attacks, pos: &Position, moves: &mut MoveList) {
                     false,
                 ));
             }
-
-            attacks_bb.pop_bit(target_square);
         }
-        bitboard.pop_bit(source_square);
     }
 }
 
 fn gen_b_king_moves(attacks: &Attacks, pos: &Position, moves: &mut MoveList) {
-    let mut bitboard = pos.get_piece_bitboard(Piece::BKing);
-    while let Some(source_square) = bitboard.get_lsb_square() {
-        let mut attacks_bb = attacks.get_king_attacks(source_square) & !pos.b_occupancies;
-        while let Some(target_square) = attacks_bb.get_lsb_square() {
+    for source_square in pos.get_piece_bitboard(Piece::BKing) {
+        for target_square in attacks.get_king_attacks(source_square) & !pos.b_occupancies {
             if pos.w_occupancies.get_bit(target_square).is_empty() {
                 // Quiet move
                 moves.push(Move::encode(
@@ -800,58 +711,90 @@ fn gen_b_king_moves(attacks: &Attacks, pos: &Position, moves: &mut MoveList) {
                     false,
                 ));
             }
-
-            attacks_bb.pop_bit(target_square);
         }
-        bitboard.pop_bit(source_square);
     }
-    // Kingside castling possible
-    if pos.castle.bk() {
-        // Make sure squares between king and  king's rook are empty
-        if pos.all_occupancies.get_bit(Square::F8).is_empty()
-            && pos.all_occupancies.get_bit(Square::G8).is_empty()
-        {
-            // Make sure king and f8 are not attacked
-            if !attacks.is_square_attacked(pos, Square::E8, Side::White)
-                && !attacks.is_square_attacked(pos, Square::F8, Side::White)
-            {
-                // Kingside castle
-                moves.push(Move::encode(
-                    Square::E8,
-                    Square::G8,
-                    Piece::BKing,
-                    Piece::None,
-                    false,
-                    false,
-                    false,
-                    true,
-                ));
-            }
+    gen_castling_moves(attacks, pos, Side::Black, moves);
+}
+
+/// Generates castling moves for any king and rook start squares, so that Chess960 positions are
+/// covered. The move target is the king's destination on the g or c file.
+fn gen_castling_moves(attacks: &Attacks, pos: &Position, side: Side, moves: &mut MoveList) {
+    for kingside in [true, false] {
+        if let Some((king_target, _, _)) = castling_squares(attacks, pos, side, kingside) {
+            moves.push(castling_move(pos, side, king_target));
         }
     }
-    // Queenside castling possible
-    if pos.castle.bq() {
-        // Make sure squares between king and queen's rook are empty
-        if pos.all_occupancies.get_bit(Square::B8).is_empty()
-            && pos.all_occupancies.get_bit(Square::C8).is_empty()
-            && pos.all_occupancies.get_bit(Square::D8).is_empty()
-        {
-            // Make sure king and d8 are not attacked
-            if !attacks.is_square_attacked(pos, Square::E8, Side::White)
-                && !attacks.is_square_attacked(pos, Square::D8, Side::White)
-            {
-                // Queenside castle
-                moves.push(Move::encode(
-                    Square::E8,
-                    Square::C8,
-                    Piece::BKing,
-                    Piece::None,
-                    false,
-                    false,
-                    false,
-                    true,
-                ));
-            }
-        }
+}
+
+/// Returns the king's destination and the rook's start and destination squares if `side` may
+/// castle on the wing. The king's destination is not checked for attacks, as the castling rook
+/// can shield it.
+fn castling_squares(
+    attacks: &Attacks,
+    pos: &Position,
+    side: Side,
+    kingside: bool,
+) -> Option<(Square, Square, Square)> {
+    if !pos.castle.has(side, kingside) {
+        return None;
     }
+    let king_source = pos.castle.king_square(side);
+    let rook_source = pos.castle.rook_square(side, kingside);
+    let rank_base = king_source as u8 & !0b111;
+    let (king_target, rook_target) = if kingside {
+        (rank_base + 6, rank_base + 5)
+    } else {
+        (rank_base + 2, rank_base + 3)
+    };
+    // Every square either piece passes over or lands on must be empty, apart from the king and
+    // rook themselves
+    let king_path = span(king_source as u8, king_target);
+    let rook_path = span(rook_source as u8, rook_target);
+    let blockers = king_path.chain(rook_path).any(|square| {
+        let square = Square::from_u8_unchecked(square);
+        square != king_source
+            && square != rook_source
+            && pos.all_occupancies.get_bit(square).is_not_empty()
+    });
+    if blockers {
+        return None;
+    }
+    // The king may not castle out of or through check
+    let attacked = span(king_source as u8, king_target)
+        .filter(|&square| square != king_target)
+        .any(|square| {
+            attacks.is_square_attacked(pos, Square::from_u8_unchecked(square), side.opponent())
+        });
+    if attacked {
+        return None;
+    }
+    Some((
+        Square::from_u8_unchecked(king_target),
+        rook_source,
+        Square::from_u8_unchecked(rook_target),
+    ))
+}
+
+#[inline]
+fn castling_move(pos: &Position, side: Side, king_target: Square) -> Move {
+    let king = match side {
+        Side::White => Piece::WKing,
+        Side::Black => Piece::BKing,
+    };
+    Move::encode(
+        pos.castle.king_square(side),
+        king_target,
+        king,
+        Piece::None,
+        false,
+        false,
+        false,
+        true,
+    )
+}
+
+/// Squares from `a` to `b` inclusive, in either order
+#[inline]
+fn span(a: u8, b: u8) -> std::ops::RangeInclusive<u8> {
+    a.min(b)..=a.max(b)
 }
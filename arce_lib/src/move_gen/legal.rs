@@ -0,0 +1,481 @@
+use crate::{
+    attacks::Attacks,
+    bitboard::BitBoard,
+    move_list::{Move, MoveList},
+    piece::{Piece, BLACK_PIECES, WHITE_PIECES},
+    position::Position,
+    side::Side,
+    square::Square,
+};
+
+use super::{castling_move, castling_squares};
+
+const ALL_SQUARES: BitBoard = BitBoard(!0);
+
+/// Which moves a generator call produces
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum GenType {
+    All,
+    /// Captures and promotions, including promotions that do not capture
+    Captures,
+    /// Moves that neither capture nor promote
+    Quiets,
+}
+
+/// Generates strictly legal moves, so that no move has to be made to find out whether it leaves
+/// the king in check. A position without legal moves gives an empty list.
+pub fn generate_legal_moves(attacks: &Attacks, pos: &Position) -> MoveList {
+    generate(attacks, pos, GenType::All)
+}
+
+/// Generates legal captures and promotions, for searches that skip quiet moves
+pub fn generate_captures(attacks: &Attacks, pos: &Position) -> MoveList {
+    generate(attacks, pos, GenType::Captures)
+}
+
+/// Generates the legal moves that `generate_captures` leaves out
+pub fn generate_quiets(attacks: &Attacks, pos: &Position) -> MoveList {
+    generate(attacks, pos, GenType::Quiets)
+}
+
+/// Generates the legal replies to a check. Out of double check only the king moves, otherwise
+/// other pieces only capture the checker or block it.
+pub fn generate_evasions(attacks: &Attacks, pos: &Position) -> MoveList {
+    let mut moves = MoveList::new();
+    let gen = LegalGen::new(attacks, pos, GenType::All);
+    debug_assert!(
+        gen.checkers.is_not_empty(),
+        "not in check: {}",
+        pos.to_fen()
+    );
+    gen.gen_king_moves(&mut moves);
+    if gen.checkers.more_than_one() {
+        return moves;
+    }
+    // The check mask limits the other pieces to the checker and the squares in between
+    gen.gen_pawn_moves(&mut moves);
+    for piece in &gen.pieces[1..5] {
+        gen.gen_piece_moves(*piece, &mut moves);
+    }
+    moves
+}
+
+fn generate(attacks: &Attacks, pos: &Position, gen_type: GenType) -> MoveList {
+    let mut moves = MoveList::new();
+    let gen = LegalGen::new(attacks, pos, gen_type);
+    // Only the king can move out of double check
+    if !gen.checkers.more_than_one() {
+        gen.gen_pawn_moves(&mut moves);
+        for piece in &gen.pieces[1..5] {
+            gen.gen_piece_moves(*piece, &mut moves);
+        }
+    }
+    gen.gen_king_moves(&mut moves);
+    if gen.checkers.is_empty() && gen_type != GenType::Captures {
+        gen.gen_castling_moves(&mut moves);
+    }
+    moves
+}
+
+/// Checkers, pins and masks of the side to move, worked out once per position
+struct LegalGen<'a> {
+    attacks: &'a Attacks,
+    pos: &'a Position,
+    gen_type: GenType,
+    side: Side,
+    /// Our pieces, pawn to king
+    pieces: [Piece; 6],
+    us: BitBoard,
+    them: BitBoard,
+    /// Their bishops and queens
+    their_diagonal: BitBoard,
+    /// Their rooks and queens
+    their_orthogonal: BitBoard,
+    king_square: Square,
+    checkers: BitBoard,
+    /// Squares other pieces than the king must move to: anywhere when not in check, otherwise
+    /// the checker or a square between it and the king
+    check_mask: BitBoard,
+    /// Squares each pinned piece can move to without exposing the king
+    pin_masks: [BitBoard; 64],
+}
+
+impl<'a> LegalGen<'a> {
+    fn new(attacks: &'a Attacks, pos: &'a Position, gen_type: GenType) -> LegalGen<'a> {
+        let side = pos.side;
+        let (pieces, their_pieces) = match side {
+            Side::White => (WHITE_PIECES, BLACK_PIECES),
+            Side::Black => (BLACK_PIECES, WHITE_PIECES),
+        };
+        let their_diagonal =
+            pos.get_piece_bitboard(their_pieces[2]) | pos.get_piece_bitboard(their_pieces[4]);
+        let their_orthogonal =
+            pos.get_piece_bitboard(their_pieces[3]) | pos.get_piece_bitboard(their_pieces[4]);
+        let mut gen = LegalGen {
+            attacks,
+            pos,
+            gen_type,
+            side,
+            pieces,
+            us: pos.get_occupancy_bitboard(side),
+            them: pos.get_occupancy_bitboard(side.opponent()),
+            their_diagonal,
+            their_orthogonal,
+            king_square: pos
+                .get_piece_bitboard(pieces[5])
+                .get_lsb_square()
+                .expect("side to move has no king"),
+            checkers: BitBoard::empty(),
+            check_mask: ALL_SQUARES,
+            pin_masks: [ALL_SQUARES; 64],
+        };
+
+        gen.checkers = gen.attackers(gen.king_square, pos.all_occupancies);
+        if let Some(checker) = gen.checkers.get_lsb_square() {
+            gen.check_mask = if !gen.checkers.more_than_one() {
+                attacks.between(gen.king_square, checker) | BitBoard::from(checker)
+            } else {
+                BitBoard::empty()
+            };
+        }
+
+        // Sliders that would attack the king if one of our pieces stepped aside
+        let pinners = (attacks.get_bishop_attacks(gen.king_square, gen.them) & their_diagonal)
+            | (attacks.get_rook_attacks(gen.king_square, gen.them) & their_orthogonal);
+        for pinner in pinners {
+            let line = attacks.between(gen.king_square, pinner);
+            let blockers = line & gen.us;
+            if blockers.popcount() == 1 {
+                let pinned = blockers.get_lsb_square().unwrap();
+                gen.pin_masks[pinned as usize] = line | BitBoard::from(pinner);
+            }
+        }
+        gen
+    }
+
+    /// Their pieces attacking a square, with sliders seeing through the given occupancy
+    #[inline]
+    fn attackers(&self, square: Square, occupancy: BitBoard) -> BitBoard {
+        self.attacks.attackers_to(self.pos, square, occupancy) & self.them
+    }
+
+    /// Squares our pawn on `square` attacks
+    #[inline]
+    fn pawn_attacks(&self, square: Square) -> BitBoard {
+        match self.side {
+            Side::White => self.attacks.get_w_pawn_attacks(square),
+            Side::Black => self.attacks.get_b_pawn_attacks(square),
+        }
+    }
+
+    #[inline]
+    fn is_capture(&self, square: Square) -> bool {
+        self.them.get_bit(square).is_not_empty()
+    }
+
+    /// Returns true if moves of this kind belong to the requested generation type
+    #[inline]
+    fn wants(&self, capture: bool, promotion: bool) -> bool {
+        match self.gen_type {
+            GenType::All => true,
+            GenType::Captures => capture || promotion,
+            GenType::Quiets => !capture && !promotion,
+        }
+    }
+
+    /// Squares pieces other than pawns may move to for the requested generation type
+    #[inline]
+    fn target_mask(&self) -> BitBoard {
+        match self.gen_type {
+            GenType::All => !self.us,
+            GenType::Captures => self.them,
+            GenType::Quiets => !self.pos.all_occupancies,
+        }
+    }
+
+    fn gen_pawn_moves(&self, moves: &mut MoveList) {
+        let pawn = self.pieces[0];
+        let (forward, start_rank, promotion_rank): (i8, u8, u8) = match self.side {
+            Side::White => (8, 1, 7),
+            Side::Black => (-8, 6, 0),
+        };
+        let push = |square: Square| Square::from_u8((square as u8).wrapping_add(forward as u8));
+        let empty = !self.pos.all_occupancies;
+
+        for source_square in self.pos.get_piece_bitboard(pawn) {
+            let mask = self.check_mask & self.pin_masks[source_square as usize];
+
+            // Quiet pawn moves
+            if let Some(target_square) =
+                push(source_square).filter(|&t| empty.get_bit(t).is_not_empty())
+            {
+                let promotion = target_square as u8 / 8 == promotion_rank;
+                if mask.get_bit(target_square).is_not_empty() && self.wants(false, promotion) {
+                    self.push_pawn_move(source_square, target_square, false, promotion_rank, moves);
+                }
+                if source_square as u8 / 8 == start_rank && self.wants(false, false) {
+                    if let Some(double_square) =
+                        push(target_square).filter(|&t| (empty & mask).get_bit(t).is_not_empty())
+                    {
+                        moves.push(Move::encode(
+                            source_square,
+                            double_square,
+                            pawn,
+                            Piece::None,
+                            false,
+                            true,
+                            false,
+                            false,
+                        ));
+                    }
+                }
+            }
+
+            if !self.wants(true, false) {
+                continue;
+            }
+
+            // Captures
+            for target_square in self.pawn_attacks(source_square) & self.them & mask {
+                self.push_pawn_move(source_square, target_square, true, promotion_rank, moves);
+            }
+
+            // En passant
+            if let Some(en_passant) = self.pos.en_passant {
+                if self
+                    .pawn_attacks(source_square)
+                    .get_bit(en_passant)
+                    .is_not_empty()
+                    && self.is_legal_en_passant(source_square, en_passant)
+                {
+                    moves.push(Move::encode(
+                        source_square,
+                        en_passant,
+                        pawn,
+                        Piece::None,
+                        true,
+                        false,
+                        true,
+                        false,
+                    ));
+                }
+            }
+        }
+    }
+
+    /// Pushes a pawn move, expanded into the four promotions on the last rank
+    #[inline]
+    fn push_pawn_move(
+        &self,
+        source_square: Square,
+        target_square: Square,
+        capture: bool,
+        promotion_rank: u8,
+        moves: &mut MoveList,
+    ) {
+        let promotions = if target_square as u8 / 8 == promotion_rank {
+            &self.pieces[1..5]
+        } else {
+            &[Piece::None][..]
+        };
+        // Queen first, as the pseudo legal generator does
+        for promoted in promotions.iter().rev() {
+            moves.push(Move::encode(
+                source_square,
+                target_square,
+                self.pieces[0],
+                *promoted,
+                capture,
+                false,
+                false,
+                false,
+            ));
+        }
+    }
+
+    /// En passant removes two pawns from one rank, so rather than using pin masks the king is
+    /// checked for slider attacks on the board as it would be after the capture
+    fn is_legal_en_passant(&self, source_square: Square, target_square: Square) -> bool {
+        let captured_square = match self.side {
+            Side::White => Square::from_u8_unchecked(target_square as u8 - 8),
+            Side::Black => Square::from_u8_unchecked(target_square as u8 + 8),
+        };
+        // A check can only be answered by capturing the checking pawn or blocking on the target
+        let resolves = BitBoard::from(target_square) | BitBoard::from(captured_square);
+        if (self.check_mask & resolves).is_empty() {
+            return false;
+        }
+        let occupancy = (self.pos.all_occupancies
+            & !BitBoard::from(source_square)
+            & !BitBoard::from(captured_square))
+            | BitBoard::from(target_square);
+        (self.attacks.get_bishop_attacks(self.king_square, occupancy) & self.their_diagonal)
+            .is_empty()
+            && (self.attacks.get_rook_attacks(self.king_square, occupancy) & self.their_orthogonal)
+                .is_empty()
+    }
+
+    /// Knight, bishop, rook and queen moves
+    fn gen_piece_moves(&self, piece: Piece, moves: &mut MoveList) {
+        for source_square in self.pos.get_piece_bitboard(piece) {
+            let occupancy = self.pos.all_occupancies;
+            let targets = match piece {
+                Piece::WKnight | Piece::BKnight => self.attacks.get_knight_attacks(source_square),
+                Piece::WBishop | Piece::BBishop => {
+                    self.attacks.get_bishop_attacks(source_square, occupancy)
+                }
+                Piece::WRook | Piece::BRook => {
+                    self.attacks.get_rook_attacks(source_square, occupancy)
+                }
+                _ => self.attacks.get_queen_attacks(source_square, occupancy),
+            };
+            let targets = targets
+                & self.target_mask()
+                & self.check_mask
+                & self.pin_masks[source_square as usize];
+            for target_square in targets {
+                moves.push(Move::encode(
+                    source_square,
+                    target_square,
+                    piece,
+                    Piece::None,
+                    self.is_capture(target_square),
+                    false,
+                    false,
+                    false,
+                ));
+            }
+        }
+    }
+
+    fn gen_king_moves(&self, moves: &mut MoveList) {
+        // The king must not hide behind itself from a slider
+        let occupancy = self.pos.all_occupancies & !BitBoard::from(self.king_square);
+        for target_square in self.attacks.get_king_attacks(self.king_square) & self.target_mask() {
+            if self.attackers(target_square, occupancy).is_empty() {
+                moves.push(Move::encode(
+                    self.king_square,
+                    target_square,
+                    self.pieces[5],
+                    Piece::None,
+                    self.is_capture(target_square),
+                    false,
+                    false,
+                    false,
+                ));
+            }
+        }
+    }
+
+    fn gen_castling_moves(&self, moves: &mut MoveList) {
+        for kingside in [true, false] {
+            if let Some((king_target, rook_source, rook_target)) =
+                castling_squares(self.attacks, self.pos, self.side, kingside)
+            {
+                // The king's destination must be safe once both pieces have moved
+                let occupancy = (self.pos.all_occupancies
+                    & !BitBoard::from(self.king_square)
+                    & !BitBoard::from(rook_source))
+                    | BitBoard::from(king_target)
+                    | BitBoard::from(rook_target);
+                if self.attackers(king_target, occupancy).is_empty() {
+                    moves.push(castling_move(self.pos, self.side, king_target));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        attacks::{Attacks, ATTACKS},
+        move_gen::generate_moves,
+        move_list::Move,
+        piece::Piece,
+        position::Position,
+        utils::{
+            fen::{parse_fen, CMK_POSITION, KILLER_POSITION, START_POSITION, TRICKY_POSITION},
+            uci::parse_move,
+        },
+    };
+
+    use super::{generate_captures, generate_evasions, generate_legal_moves, generate_quiets};
+
+    /// Checks that the legal moves are exactly the pseudo legal moves that do not leave the king
+    /// in check, throughout the move tree
+    fn check_legal_moves(pos: &mut Position, attacks: &Attacks, depth: u32) {
+        let expected: Vec<Move> = generate_moves(attacks, pos)
+            .into_iter()
+            .filter(|&mov| pos.clone().make_move(mov, attacks))
+            .collect();
+        let legal: Vec<Move> = generate_legal_moves(attacks, pos).into_iter().collect();
+        assert_eq!(legal.len(), expected.len(), "{}", pos.to_fen());
+        for mov in legal.iter() {
+            assert!(expected.contains(mov), "{} in {}", mov, pos.to_fen());
+        }
+
+        // The staged generators split the legal moves between them
+        let captures = generate_captures(attacks, pos);
+        let quiets = generate_quiets(attacks, pos);
+        assert_eq!(
+            captures.len() + quiets.len(),
+            legal.len(),
+            "{}",
+            pos.to_fen()
+        );
+        for mov in captures {
+            assert!(mov.extract_capture() || mov.extract_promoted_piece() != Piece::None);
+            assert!(legal.contains(&mov), "{} in {}", mov, pos.to_fen());
+        }
+        for mov in quiets {
+            assert!(!mov.extract_capture() && mov.extract_promoted_piece() == Piece::None);
+            assert!(legal.contains(&mov), "{} in {}", mov, pos.to_fen());
+        }
+        if pos.in_check(attacks) {
+            let evasions = generate_evasions(attacks, pos);
+            assert_eq!(evasions.len(), legal.len(), "{}", pos.to_fen());
+            for mov in evasions {
+                assert!(legal.contains(&mov), "{} in {}", mov, pos.to_fen());
+            }
+        }
+        if depth == 0 {
+            return;
+        }
+        for mov in generate_legal_moves(attacks, pos) {
+            let undo = pos.make_move_unchecked(mov);
+            check_legal_moves(pos, attacks, depth - 1);
+            pos.unmake_move(mov, undo);
+        }
+    }
+
+    #[test]
+    fn test_legal_moves() {
+        for fen in [
+            START_POSITION,
+            TRICKY_POSITION,
+            KILLER_POSITION,
+            CMK_POSITION,
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+            "b1q1rrkb/pppppppp/3nn3/8/P7/1PPP4/4PPPP/BQNNRKRB w GE - 1 9",
+            // En passant exposing the king along the rank
+            "8/8/8/K2pP2r/8/8/8/7k w - d6 0 1",
+        ] {
+            check_legal_moves(&mut parse_fen(fen).unwrap(), &ATTACKS, 2);
+        }
+    }
+
+    #[test]
+    fn test_more_moves_than_capacity() {
+        // Fails validation, but generating moves for it must not panic
+        let mut pos = parse_fen("QQQQQQnK/Q5QQ/Q6Q/Q6Q/p6Q/QQ5Q/ppQ4Q/kbQQQQQQ w - - 0 1").unwrap();
+        assert!(!pos.validate(&ATTACKS).is_empty());
+        generate_moves(&ATTACKS, &pos);
+        generate_legal_moves(&ATTACKS, &pos);
+        let mov = parse_move("c2b1", &pos, &pos.legal_moves(), false).unwrap();
+        assert!(pos.play(mov));
+        generate_moves(&ATTACKS, &pos);
+        generate_legal_moves(&ATTACKS, &pos);
+    }
+}
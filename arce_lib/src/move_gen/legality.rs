@@ -0,0 +1,291 @@
+use crate::{
+    attacks::{Attacks, ATTACKS},
+    bitboard::BitBoard,
+    move_list::Move,
+    piece::{Piece, BLACK_PIECES, WHITE_PIECES},
+    position::Position,
+    side::Side,
+    square::Square,
+};
+
+use super::castling_squares;
+
+impl Position {
+    /// Returns true if `generate_moves` would generate the move in this position. Moves from a
+    /// transposition table, killer slot, book or GUI can be stale or corrupt, and making one
+    /// that fails this check would corrupt the position.
+    pub fn is_pseudo_legal_with(&self, mov: Move, attacks: &Attacks) -> bool {
+        let source_square = mov.extract_source();
+        let target_square = mov.extract_target();
+        let piece = mov.extract_piece();
+        let promoted_piece = mov.extract_promoted_piece();
+        let capture = mov.extract_capture();
+        let double_push = mov.extract_double_push();
+        let en_passant = mov.extract_en_passant();
+        let pieces = match self.side {
+            Side::White => WHITE_PIECES,
+            Side::Black => BLACK_PIECES,
+        };
+
+        // The moving piece must be ours and on the source square
+        if !pieces.contains(&piece) || self.piece_on(source_square) != Some(piece) {
+            return false;
+        }
+
+        if mov.extract_castling() {
+            let kingside = target_square as u8 & 0b111 == 6;
+            return piece == pieces[5]
+                && !capture
+                && !double_push
+                && !en_passant
+                && promoted_piece == Piece::None
+                && source_square == self.castle.king_square(self.side)
+                && castling_squares(attacks, self, self.side, kingside)
+                    .is_some_and(|(king_target, _, _)| king_target == target_square);
+        }
+
+        if self
+            .get_occupancy_bitboard(self.side)
+            .get_bit(target_square)
+            .is_not_empty()
+        {
+            return false;
+        }
+        let pawn_attacks = match self.side {
+            Side::White => attacks.get_w_pawn_attacks(source_square),
+            Side::Black => attacks.get_b_pawn_attacks(source_square),
+        };
+
+        if en_passant {
+            return piece == pieces[0]
+                && capture
+                && !double_push
+                && promoted_piece == Piece::None
+                && self.en_passant == Some(target_square)
+                && pawn_attacks.get_bit(target_square).is_not_empty();
+        }
+
+        let occupied_by_them = self
+            .get_occupancy_bitboard(self.side.opponent())
+            .get_bit(target_square)
+            .is_not_empty();
+        if capture != occupied_by_them {
+            return false;
+        }
+
+        if piece == pieces[0] {
+            let (forward, start_rank, promotion_rank): (i8, u8, u8) = match self.side {
+                Side::White => (8, 1, 7),
+                Side::Black => (-8, 6, 0),
+            };
+            if target_square as u8 / 8 == promotion_rank {
+                if !pieces[1..5].contains(&promoted_piece) {
+                    return false;
+                }
+            } else if promoted_piece != Piece::None {
+                return false;
+            }
+            if capture {
+                return !double_push && pawn_attacks.get_bit(target_square).is_not_empty();
+            }
+            let push = |square: Square| Square::from_u8((square as u8).wrapping_add(forward as u8));
+            let single = match push(source_square) {
+                Some(square) if self.piece_on(square).is_none() => square,
+                _ => return false,
+            };
+            if double_push {
+                source_square as u8 / 8 == start_rank && push(single) == Some(target_square)
+            } else {
+                single == target_square
+            }
+        } else {
+            if promoted_piece != Piece::None || double_push {
+                return false;
+            }
+            let occupancy = self.all_occupancies;
+            let reach = match piece {
+                Piece::WKnight | Piece::BKnight => attacks.get_knight_attacks(source_square),
+                Piece::WBishop | Piece::BBishop => {
+                    attacks.get_bishop_attacks(source_square, occupancy)
+                }
+                Piece::WRook | Piece::BRook => attacks.get_rook_attacks(source_square, occupancy),
+                Piece::WQueen | Piece::BQueen => {
+                    attacks.get_queen_attacks(source_square, occupancy)
+                }
+                _ => attacks.get_king_attacks(source_square),
+            };
+            reach.get_bit(target_square).is_not_empty()
+        }
+    }
+
+    /// `is_pseudo_legal_with` using the global `ATTACKS` tables
+    #[inline]
+    pub fn is_pseudo_legal(&self, mov: Move) -> bool {
+        self.is_pseudo_legal_with(mov, &ATTACKS)
+    }
+
+    /// Returns true if the move is pseudo legal and does not leave the king in check, so that
+    /// it is one of the moves `generate_legal_moves` would generate. Rather than making the
+    /// move, the king is checked for attackers with the occupancy the move would leave.
+    pub fn is_legal_with(&self, mov: Move, attacks: &Attacks) -> bool {
+        if !self.is_pseudo_legal_with(mov, attacks) {
+            return false;
+        }
+        let source_square = mov.extract_source();
+        let target_square = mov.extract_target();
+        let king = match self.side {
+            Side::White => Piece::WKing,
+            Side::Black => Piece::BKing,
+        };
+        let (king_square, captured_square, occupancy) = if mov.extract_castling() {
+            // `is_pseudo_legal_with` checked the king's path, leaving its destination to be checked
+            // once both pieces have moved
+            let kingside = target_square as u8 & 0b111 == 6;
+            let rook_source = self.castle.rook_square(self.side, kingside);
+            let rook_target = Square::from_u8_unchecked(
+                (target_square as u8 & !0b111) + if kingside { 5 } else { 3 },
+            );
+            let occupancy = (self.all_occupancies
+                & !BitBoard::from(source_square)
+                & !BitBoard::from(rook_source))
+                | BitBoard::from(target_square)
+                | BitBoard::from(rook_target);
+            (target_square, None, occupancy)
+        } else {
+            let king_square = if mov.extract_piece() == king {
+                target_square
+            } else {
+                self.get_piece_bitboard(king)
+                    .get_lsb_square()
+                    .expect("side to move has no king")
+            };
+            let captured_square = if mov.extract_en_passant() {
+                Some(match self.side {
+                    Side::White => Square::from_u8_unchecked(target_square as u8 - 8),
+                    Side::Black => Square::from_u8_unchecked(target_square as u8 + 8),
+                })
+            } else if mov.extract_capture() {
+                Some(target_square)
+            } else {
+                None
+            };
+            let mut occupancy = self.all_occupancies & !BitBoard::from(source_square);
+            if let Some(square) = captured_square {
+                occupancy.pop_bit(square);
+            }
+            (
+                king_square,
+                captured_square,
+                occupancy | BitBoard::from(target_square),
+            )
+        };
+
+        // A captured piece no longer attacks, and the occupancy hides sliders that are blocked
+        let mut them = self.get_occupancy_bitboard(self.side.opponent());
+        if let Some(square) = captured_square {
+            them.pop_bit(square);
+        }
+        (attacks.attackers_to(self, king_square, occupancy) & them).is_empty()
+    }
+
+    /// `is_legal_with` using the global `ATTACKS` tables
+    #[inline]
+    pub fn is_legal(&self, mov: Move) -> bool {
+        self.is_legal_with(mov, &ATTACKS)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        attacks::{Attacks, ATTACKS},
+        move_gen::{generate_legal_moves, generate_moves},
+        move_list::Move,
+        piece::Piece,
+        position::Position,
+        square::Square,
+        utils::fen::{parse_fen, CMK_POSITION, KILLER_POSITION, START_POSITION, TRICKY_POSITION},
+    };
+
+    const POSITIONS: [&str; 7] = [
+        START_POSITION,
+        TRICKY_POSITION,
+        KILLER_POSITION,
+        CMK_POSITION,
+        "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+        "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+        "b1q1rrkb/pppppppp/3nn3/8/P7/1PPP4/4PPPP/BQNNRKRB w GE - 1 9",
+    ];
+
+    /// Checks moves taken from other positions, which are mostly stale here
+    fn check_moves(pos: &Position, moves: &[Move], attacks: &Attacks) {
+        let pseudo_legal = generate_moves(attacks, pos);
+        let legal = generate_legal_moves(attacks, pos);
+        for &mov in moves {
+            assert_eq!(
+                pos.is_pseudo_legal_with(mov, attacks),
+                pseudo_legal.contains(mov),
+                "pseudo legal {} in {}",
+                mov,
+                pos.to_fen()
+            );
+            assert_eq!(
+                pos.is_legal_with(mov, attacks),
+                legal.contains(mov),
+                "legal {} in {}",
+                mov,
+                pos.to_fen()
+            );
+        }
+    }
+
+    #[test]
+    fn test_stale_moves() {
+        let positions: Vec<Position> = POSITIONS.iter().map(|f| parse_fen(f).unwrap()).collect();
+        let mut moves: Vec<Move> = Vec::new();
+        for pos in positions.iter() {
+            moves.extend(generate_moves(&ATTACKS, pos));
+        }
+        // Corrupted flags on otherwise sensible moves
+        moves.push(Move::encode(
+            Square::E2,
+            Square::E4,
+            Piece::WPawn,
+            Piece::None,
+            false,
+            false,
+            false,
+            false,
+        ));
+        moves.push(Move::encode(
+            Square::G1,
+            Square::F3,
+            Piece::WKnight,
+            Piece::None,
+            true,
+            false,
+            false,
+            false,
+        ));
+        moves.push(Move::encode(
+            Square::E1,
+            Square::G1,
+            Piece::WKing,
+            Piece::None,
+            false,
+            false,
+            false,
+            false,
+        ));
+        moves.push(Move::empty());
+
+        for pos in positions.iter() {
+            check_moves(pos, &moves, &ATTACKS);
+            for mov in generate_legal_moves(&ATTACKS, pos) {
+                let mut child = pos.clone();
+                child.make_move_unchecked(mov);
+                check_moves(&child, &moves, &ATTACKS);
+            }
+        }
+    }
+}
@@ -1,37 +1,110 @@
-use std::{fmt::Display, ops::Index, usize};
+use std::{fmt::Display, ops::Index};
 
 use crate::{piece::Piece, square::Square};
 
+/// Capacity of a `MoveList`. No legal position has more than 218 legal moves, and pseudo legal
+/// moves stay below this too, but unvalidated positions can have more and get truncated.
+pub const MAX_MOVES: usize = 256;
+
+/// Fixed-capacity list of moves kept on the stack, with a score slot per move for ordering
+#[derive(Clone)]
 pub struct MoveList {
-    pub moves: Vec<Move>,
+    moves: [Move; MAX_MOVES],
+    scores: [i32; MAX_MOVES],
+    len: usize,
 }
 
 impl MoveList {
     pub const fn new() -> MoveList {
-        MoveList { moves: Vec::new() }
-    }
-
-    pub fn with_capacity(capacity: usize) -> MoveList {
         MoveList {
-            moves: Vec::with_capacity(capacity),
+            moves: [Move::empty(); MAX_MOVES],
+            scores: [0; MAX_MOVES],
+            len: 0,
         }
     }
 
+    /// Kept for compatibility, the capacity is always `MAX_MOVES`
+    pub const fn with_capacity(_capacity: usize) -> MoveList {
+        MoveList::new()
+    }
+
+    #[inline]
     pub fn clear(&mut self) {
-        self.moves.clear();
+        self.len = 0;
     }
 
+    /// Adds a move with a score of zero. Moves past `MAX_MOVES` are dropped, which can only
+    /// happen in positions that fail validation.
+    #[inline]
     pub fn push(&mut self, mov: Move) {
-        self.moves.push(mov);
+        if self.len == MAX_MOVES {
+            return;
+        }
+        self.moves[self.len] = mov;
+        self.scores[self.len] = 0;
+        self.len += 1;
     }
 
+    #[inline]
     pub fn pop(&mut self) -> Option<Move> {
-        self.moves.pop()
+        if self.len == 0 {
+            return None;
+        }
+        self.len -= 1;
+        Some(self.moves[self.len])
+    }
+
+    #[inline]
+    pub const fn len(&self) -> usize {
+        self.len
+    }
+
+    #[inline]
+    pub const fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    #[inline]
+    pub fn as_slice(&self) -> &[Move] {
+        &self.moves[..self.len]
+    }
+
+    /// The moves in the list, same as `as_slice`
+    #[inline]
+    pub fn moves(&self) -> &[Move] {
+        self.as_slice()
+    }
+
+    #[inline]
+    pub fn iter(&self) -> std::slice::Iter<'_, Move> {
+        self.as_slice().iter()
+    }
+
+    #[inline]
+    pub fn contains(&self, mov: Move) -> bool {
+        self.as_slice().contains(&mov)
+    }
+
+    #[inline]
+    pub fn score(&self, index: usize) -> i32 {
+        self.scores[..self.len][index]
+    }
+
+    #[inline]
+    pub fn set_score(&mut self, index: usize, score: i32) {
+        self.scores[..self.len][index] = score;
+    }
+
+    /// Swaps two moves together with their scores
+    #[inline]
+    pub fn swap(&mut self, a: usize, b: usize) {
+        self.moves[..self.len].swap(a, b);
+        self.scores.swap(a, b);
     }
 
     pub fn print_move_list(&self) {
         println!(" idx:  move:  piece:  prompted:  capture:  double:  en passant:  castling:");
-        self.moves.iter().enumerate().for_each(|(i, m)| {
+        self.iter().enumerate().for_each(|(i, m)| {
             println!(
                 " {:03}   {:7}{:8}{:11}{:10}{:9}{:13}{}",
                 i,
@@ -44,28 +117,72 @@ impl MoveList {
                 m.extract_castling()
             )
         });
-        println!(" Number of moves: {}", self.moves.len());
+        println!(" Number of moves: {}", self.len);
+    }
+}
+
+impl Default for MoveList {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 impl Index<usize> for MoveList {
     type Output = Move;
 
+    #[inline]
     fn index(&self, index: usize) -> &Self::Output {
-        &self.moves[index]
+        &self.as_slice()[index]
     }
 }
 
 impl IntoIterator for MoveList {
     type Item = Move;
 
-    type IntoIter = std::vec::IntoIter<Self::Item>;
+    type IntoIter = IntoIter;
 
     fn into_iter(self) -> Self::IntoIter {
-        self.moves.into_iter()
+        IntoIter {
+            list: self,
+            index: 0,
+        }
+    }
+}
+
+impl<'a> IntoIterator for &'a MoveList {
+    type Item = &'a Move;
+
+    type IntoIter = std::slice::Iter<'a, Move>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// Owning iterator over a `MoveList`
+pub struct IntoIter {
+    list: MoveList,
+    index: usize,
+}
+
+impl Iterator for IntoIter {
+    type Item = Move;
+
+    #[inline]
+    fn next(&mut self) -> Option<Move> {
+        let mov = self.list.as_slice().get(self.index).copied();
+        self.index += 1;
+        mov
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.list.len.saturating_sub(self.index);
+        (remaining, Some(remaining))
     }
 }
 
+impl ExactSizeIterator for IntoIter {}
+
 #[derive(Clone, Copy, PartialEq, Eq)]
 pub struct Move(u32);
 
@@ -164,7 +281,7 @@ impl Display for Move {
 mod test {
     use crate::{piece::Piece, square::Square};
 
-    use super::Move;
+    use super::{Move, MoveList, MAX_MOVES};
 
     #[test]
     fn test_encode_decode_move() {
@@ -187,4 +304,49 @@ mod test {
         assert!(m.extract_en_passant());
         assert!(m.extract_castling());
     }
+
+    #[test]
+    fn test_move_list() {
+        let quiet = |target| {
+            Move::encode(
+                Square::A1,
+                target,
+                Piece::WRook,
+                Piece::None,
+                false,
+                false,
+                false,
+                false,
+            )
+        };
+        let mut list = MoveList::new();
+        assert!(list.is_empty());
+        for target in [Square::A2, Square::A3, Square::A4] {
+            list.push(quiet(target));
+        }
+        assert_eq!(list.len(), 3);
+        assert!(list[1] == quiet(Square::A3));
+        assert!(list.contains(quiet(Square::A4)));
+
+        // Scores move with their moves
+        list.set_score(2, 7);
+        list.swap(0, 2);
+        assert!(list[0] == quiet(Square::A4));
+        assert_eq!((list.score(0), list.score(2)), (7, 0));
+
+        assert!(list.pop() == Some(quiet(Square::A2)));
+        let targets: Vec<Square> = list.into_iter().map(|m| m.extract_target()).collect();
+        assert_eq!(targets, vec![Square::A4, Square::A3]);
+
+        // Room for the most moves a position can have
+        let mut list = MoveList::new();
+        for _ in 0..MAX_MOVES {
+            list.push(quiet(Square::H1));
+        }
+        assert_eq!(list.len(), MAX_MOVES);
+        list.push(quiet(Square::H2));
+        assert_eq!(list.len(), MAX_MOVES);
+        assert!(list.moves().iter().all(|&m| m == quiet(Square::H1)));
+        assert!(MoveList::with_capacity(8).is_empty());
+    }
 }
@@ -0,0 +1,244 @@
+use crate::{
+    attacks::Attacks,
+    move_gen::{generate_captures, generate_quiets},
+    move_list::{Move, MoveList},
+    piece::Piece,
+    position::Position,
+};
+
+/// Stages of a `MovePicker`, in the order their moves are yielded
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Stage {
+    HashMove,
+    Captures,
+    Killers,
+    Quiets,
+    Done,
+}
+
+/// Yields the legal moves of a position best guess first: the hash move, then captures and
+/// promotions by most valuable victim and least valuable attacker, then the killer moves and
+/// finally the remaining quiet moves.
+///
+/// Captures and quiets are generated separately and only once they are needed, so a cutoff
+/// early on saves generating the rest.
+pub struct MovePicker {
+    stage: Stage,
+    hash_move: Move,
+    killers: [Move; 2],
+    captures: Option<MoveList>,
+    quiets: Option<MoveList>,
+    index: usize,
+}
+
+impl MovePicker {
+    /// `hash_move` and `killers` may be `Move::empty()`, or moves that are not legal here, in
+    /// which case they are skipped
+    pub fn new(hash_move: Move, killers: [Move; 2]) -> MovePicker {
+        MovePicker {
+            stage: Stage::HashMove,
+            hash_move,
+            killers,
+            captures: None,
+            quiets: None,
+            index: 0,
+        }
+    }
+
+    /// Returns the next move, or None once every legal move has been yielded
+    pub fn next(&mut self, attacks: &Attacks, pos: &Position) -> Option<Move> {
+        loop {
+            match self.stage {
+                Stage::HashMove => {
+                    self.stage = Stage::Captures;
+                    let hash_move = self.hash_move;
+                    if hash_move != Move::empty() && pos.is_legal_with(hash_move, attacks) {
+                        return Some(hash_move);
+                    }
+                }
+                Stage::Captures => {
+                    self.captures(attacks, pos);
+                    while let Some(mov) = self.pick_capture() {
+                        if mov != self.hash_move {
+                            return Some(mov);
+                        }
+                    }
+                    self.stage = Stage::Killers;
+                    self.index = 0;
+                }
+                Stage::Killers => {
+                    while self.index < self.killers.len() {
+                        let killer = self.killers[self.index];
+                        self.index += 1;
+                        if killer != Move::empty()
+                            && killer != self.hash_move
+                            && !is_tactical(killer)
+                            && pos.is_legal_with(killer, attacks)
+                        {
+                            return Some(killer);
+                        }
+                    }
+                    self.stage = Stage::Quiets;
+                    self.index = 0;
+                }
+                Stage::Quiets => {
+                    let hash_move = self.hash_move;
+                    let killers = self.killers;
+                    let index = self.index;
+                    let quiets = self.quiets(attacks, pos);
+                    if let Some(offset) = quiets.as_slice()[index..]
+                        .iter()
+                        .position(|mov| *mov != hash_move && !killers.contains(mov))
+                    {
+                        let mov = quiets[index + offset];
+                        self.index = index + offset + 1;
+                        return Some(mov);
+                    }
+                    self.stage = Stage::Done;
+                }
+                Stage::Done => return None,
+            }
+        }
+    }
+
+    /// Generates the captures the first time they are needed
+    fn captures(&mut self, attacks: &Attacks, pos: &Position) -> &MoveList {
+        if self.captures.is_none() {
+            let mut captures = generate_captures(attacks, pos);
+            for i in 0..captures.len() {
+                captures.set_score(i, mvv_lva(captures[i], pos));
+            }
+            self.captures = Some(captures);
+        }
+        self.captures.as_ref().unwrap()
+    }
+
+    /// Generates the quiet moves the first time they are needed
+    fn quiets(&mut self, attacks: &Attacks, pos: &Position) -> &MoveList {
+        if self.quiets.is_none() {
+            self.quiets = Some(generate_quiets(attacks, pos));
+        }
+        self.quiets.as_ref().unwrap()
+    }
+
+    /// Selects the best scoring capture not yet yielded, moving it to the front of the remaining
+    /// ones so that only as much of the list is sorted as is used
+    fn pick_capture(&mut self) -> Option<Move> {
+        let captures = self.captures.as_mut()?;
+        let index = self.index;
+        let best = (index..captures.len()).max_by_key(|&i| captures.score(i))?;
+        captures.swap(index, best);
+        self.index += 1;
+        Some(captures[index])
+    }
+}
+
+/// Captures and promotions, which are yielded in the captures stage
+#[inline]
+fn is_tactical(mov: Move) -> bool {
+    mov.extract_capture() || mov.extract_promoted_piece() != Piece::None
+}
+
+/// Orders captures by most valuable victim, then by least valuable attacker. Promotions count
+/// the promoted piece as a second victim.
+fn mvv_lva(mov: Move, pos: &Position) -> i32 {
+    // Piece types from pawn to king
+    let piece_type = |piece: Piece| piece as i32 % 6;
+    let mut score = -piece_type(mov.extract_piece());
+    if mov.extract_capture() {
+        let victim = if mov.extract_en_passant() {
+            0
+        } else {
+            pos.piece_on(mov.extract_target()).map_or(0, piece_type)
+        };
+        score += 10 * (victim + 1);
+    }
+    if mov.extract_promoted_piece() != Piece::None {
+        score += 10 * piece_type(mov.extract_promoted_piece());
+    }
+    score
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        attacks::{Attacks, ATTACKS},
+        move_gen::generate_legal_moves,
+        move_list::Move,
+        piece::Piece,
+        position::Position,
+        square::Square,
+        utils::fen::{parse_fen, KILLER_POSITION, START_POSITION, TRICKY_POSITION},
+    };
+
+    use super::{is_tactical, mvv_lva, MovePicker};
+
+    fn find(pos: &Position, attacks: &Attacks, mov: &str) -> Move {
+        generate_legal_moves(attacks, pos)
+            .into_iter()
+            .find(|m| m.to_string() == mov)
+            .unwrap()
+    }
+
+    fn pick_all(picker: &mut MovePicker, pos: &Position, attacks: &Attacks) -> Vec<Move> {
+        let mut moves = Vec::new();
+        while let Some(mov) = picker.next(attacks, pos) {
+            moves.push(mov);
+        }
+        moves
+    }
+
+    #[test]
+    fn test_picks_every_legal_move_once() {
+        for fen in [START_POSITION, TRICKY_POSITION, KILLER_POSITION] {
+            let pos = parse_fen(fen).unwrap();
+            let legal = generate_legal_moves(&ATTACKS, &pos);
+            let picked = pick_all(
+                &mut MovePicker::new(Move::empty(), [Move::empty(); 2]),
+                &pos,
+                &ATTACKS,
+            );
+            assert_eq!(picked.len(), legal.len(), "{}", fen);
+            for mov in legal {
+                assert!(picked.contains(&mov), "{} not picked in {}", mov, fen);
+            }
+        }
+    }
+
+    #[test]
+    fn test_stage_order() {
+        let pos = parse_fen(TRICKY_POSITION).unwrap();
+        let hash_move = find(&pos, &ATTACKS, "a2a3");
+        let killer = find(&pos, &ATTACKS, "e1g1");
+        // Not legal here, so it must be skipped
+        let bad_killer = Move::encode(
+            Square::A1,
+            Square::A8,
+            Piece::WRook,
+            Piece::None,
+            false,
+            false,
+            false,
+            false,
+        );
+        let picked = pick_all(
+            &mut MovePicker::new(hash_move, [bad_killer, killer]),
+            &pos,
+            &ATTACKS,
+        );
+
+        assert_eq!(picked.len(), generate_legal_moves(&ATTACKS, &pos).len());
+        assert!(picked[0] == hash_move);
+        let captures = picked[1..]
+            .iter()
+            .take_while(|mov| is_tactical(**mov))
+            .count();
+        assert!(captures > 0);
+        for pair in picked[1..=captures].windows(2) {
+            assert!(mvv_lva(pair[0], &pos) >= mvv_lva(pair[1], &pos));
+        }
+        assert!(picked[captures + 1] == killer);
+        assert!(picked[captures + 2..].iter().all(|mov| !is_tactical(*mov)));
+        assert!(!picked.contains(&bad_killer));
+    }
+}
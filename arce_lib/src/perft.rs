@@ -1,25 +1,230 @@
-use std::{mem, time::Instant};
+use std::{
+    fmt::Display,
+    mem,
+    ops::AddAssign,
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+    thread,
+    time::Instant,
+};
 
-use crate::{attacks::Attacks, move_gen, position::Position};
+use crate::{
+    attacks::{Attacks, ATTACKS},
+    bitboard::BitBoard,
+    move_gen,
+    move_list::Move,
+    piece::Piece,
+    position::Position,
+    square::Square,
+};
 
+/// Settings for `perft_divide` and `perft_fast`
+#[derive(Clone, Copy, Debug)]
+pub struct PerftOptions {
+    /// Number of threads the root moves are split across
+    pub threads: usize,
+    /// Size of the perft hash table in megabytes, 0 to not use one
+    pub hash_mb: usize,
+}
+
+impl Default for PerftOptions {
+    fn default() -> PerftOptions {
+        PerftOptions {
+            threads: 1,
+            hash_mb: 0,
+        }
+    }
+}
+
+/// Runs a performance test with bulk counting, a 64MB hash table and a thread per CPU
 pub fn perft_test(pos: Position, depth: u32) {
     println!("Performance Test:");
+    let options = PerftOptions {
+        threads: thread::available_parallelism().map_or(1, |n| n.get()),
+        hash_mb: 64,
+    };
+    let start_time = Instant::now();
+    let divide = perft_divide(&pos, depth, &options);
+    let time = start_time.elapsed();
+    for (mov, nodes) in divide.iter() {
+        println!(" move: {:5}  nodes: {}", mov, nodes);
+    }
+    println!("Depth: {}", depth);
+    println!(
+        "Nodes: {}",
+        divide.iter().map(|(_, nodes)| nodes).sum::<u64>()
+    );
+    println!("Time: {:02?}", time);
+}
+
+/// Runs a performance test that breaks the leaf nodes down into captures, checks and the other
+/// categories of `PerftStats`, per root move and in total
+pub fn perft_test_stats(pos: Position, depth: u32) {
+    println!("Performance Test:");
+    let options = PerftOptions {
+        threads: thread::available_parallelism().map_or(1, |n| n.get()),
+        hash_mb: 0,
+    };
+    let start_time = Instant::now();
+    let divide = perft_divide_stats(&pos, depth, &options);
+    let time = start_time.elapsed();
+    let mut total = PerftStats::default();
+    for (mov, stats) in divide {
+        println!(" move: {:5}  {}", mov, stats);
+        total += stats;
+    }
+    println!("Depth: {}", depth);
+    println!("Total: {}", total);
+    println!("Time: {:02?}", time);
+}
+
+/// Runs a performance test using make/unmake on every leaf, on one thread
+pub fn perft_test_serial(pos: Position, depth: u32) {
+    run_perft_test(pos, depth, false)
+}
+
+/// Runs a performance test using copy-make, to compare against `perft_test`
+pub fn perft_test_copy_make(pos: Position, depth: u32) {
+    run_perft_test(pos, depth, true)
+}
+
+/// Returns the number of leaf nodes at the given depth, making every move down to the leaves on
+/// one thread. `perft_fast` gives the same count much faster.
+pub fn perft(pos: Position, attacks: &Attacks, depth: u32) -> u64 {
     let mut p = Perft::new(pos);
-    let attacks = Attacks::gen();
-    let moves = move_gen::generate_moves(&attacks, &p.pos);
+    p.perft_driver(attacks, depth);
+    p.nodes
+}
+
+/// Returns the number of leaf nodes at the given depth, counted with `perft_divide`
+pub fn perft_fast(pos: &Position, depth: u32, options: &PerftOptions) -> u64 {
+    if depth == 0 {
+        return 1;
+    }
+    perft_divide(pos, depth, options)
+        .iter()
+        .map(|(_, nodes)| nodes)
+        .sum()
+}
+
+/// Returns the number of leaf nodes below each legal move, in generation order. The last ply is
+/// bulk counted from the length of the legal move list instead of making its moves, and the root
+/// moves are shared out between `options.threads` threads, which also share the hash table.
+pub fn perft_divide(pos: &Position, depth: u32, options: &PerftOptions) -> Vec<(Move, u64)> {
+    if depth == 0 {
+        return Vec::new();
+    }
+    let table = if options.hash_mb > 0 {
+        Some(PerftTable::new(options.hash_mb))
+    } else {
+        None
+    };
+    split_root_moves(pos, options.threads, |p, _| {
+        p.perft_driver_bulk(&ATTACKS, depth - 1, table.as_ref())
+    })
+}
+
+/// Returns the statistics of the leaf nodes below each legal move, in generation order. Every
+/// leaf move has to be made to classify it, so there is no bulk counting or hash table, but the
+/// root moves are still shared out between `options.threads` threads.
+pub fn perft_divide_stats(
+    pos: &Position,
+    depth: u32,
+    options: &PerftOptions,
+) -> Vec<(Move, PerftStats)> {
+    if depth == 0 {
+        return Vec::new();
+    }
+    split_root_moves(pos, options.threads, |p, mov| {
+        let mut stats = PerftStats::default();
+        if depth == 1 {
+            stats.record(mov, &p.pos, &ATTACKS);
+        } else {
+            p.perft_driver_stats(&ATTACKS, depth - 1, &mut stats);
+        }
+        stats
+    })
+}
+
+/// Returns the statistics of the leaf nodes at the given depth. Checks, checkmates and the
+/// other categories are those of the moves into the leaves, as in published perft tables.
+pub fn perft_stats(pos: &Position, depth: u32, options: &PerftOptions) -> PerftStats {
+    if depth == 0 {
+        return PerftStats {
+            nodes: 1,
+            ..PerftStats::default()
+        };
+    }
+    perft_divide_stats(pos, depth, options).into_iter().fold(
+        PerftStats::default(),
+        |mut total, (_, stats)| {
+            total += stats;
+            total
+        },
+    )
+}
+
+/// Shares the root moves out between `threads` threads. `count` is called with each root move,
+/// after making it on the thread's own copy of the position, and the results are returned in generation order.
+fn split_root_moves<T: Send>(
+    pos: &Position,
+    threads: usize,
+    count: impl Fn(&mut Perft, Move) -> T + Sync,
+) -> Vec<(Move, T)> {
+    let moves = move_gen::generate_legal_moves(&ATTACKS, pos);
+    // Index of the next root move to hand out
+    let next = AtomicUsize::new(0);
+
+    let mut results: Vec<(usize, T)> = thread::scope(|scope| {
+        let handles: Vec<_> = (0..threads.max(1).min(moves.len()))
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut p = Perft::new(pos.clone());
+                    let mut results = Vec::new();
+                    loop {
+                        let index = next.fetch_add(1, Ordering::Relaxed);
+                        let mov = match moves.as_slice().get(index) {
+                            Some(&mov) => mov,
+                            None => break,
+                        };
+                        let undo = p.pos.make_move_unchecked(mov);
+                        results.push((index, count(&mut p, mov)));
+                        p.pos.unmake_move(mov, undo);
+                    }
+                    results
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap())
+            .collect()
+    });
+
+    results.sort_unstable_by_key(|(index, _)| *index);
+    results
+        .into_iter()
+        .map(|(index, result)| (moves[index], result))
+        .collect()
+}
+
+fn run_perft_test(pos: Position, depth: u32, copy_make: bool) {
+    println!("Performance Test:");
+    let mut p = Perft::new(pos);
+    let moves = move_gen::generate_legal_moves(&ATTACKS, &p.pos);
     // Start timer
     let start_time = Instant::now();
     // Find perft of all legal moves
-    for mov in moves.moves.iter() {
-        let copy = p.pos.clone();
-        if !p.pos.make_move(*mov, &attacks) {
-            continue;
-        }
+    for mov in moves {
+        let undo = p.pos.make_move_unchecked(mov);
         let prev_nodes = p.nodes;
-        p.perft_driver(&attacks, depth - 1);
+        if copy_make {
+            p.perft_driver_copy_make(&ATTACKS, depth - 1);
+        } else {
+            p.perft_driver(&ATTACKS, depth - 1);
+        }
         let new_nodes = p.nodes - prev_nodes;
         // Reset pos
-        let _ = mem::replace(&mut p.pos, copy);
+        p.pos.unmake_move(mov, undo);
         println!(" move: {:5}  nodes: {}", mov, new_nodes);
     }
     let time = start_time.elapsed();
@@ -28,6 +233,87 @@ pub fn perft_test(pos: Position, depth: u32) {
     println!("Time: {:02?}", time);
 }
 
+/// Leaf node counts broken down the way published perft tables do. Captures include en passant,
+/// discovered checks are single checks given by a piece other than the one that moved, and double
+/// checks are not counted as discovered checks.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PerftStats {
+    pub nodes: u64,
+    pub captures: u64,
+    pub en_passants: u64,
+    pub castles: u64,
+    pub promotions: u64,
+    pub checks: u64,
+    pub discovered_checks: u64,
+    pub double_checks: u64,
+    pub checkmates: u64,
+}
+
+impl PerftStats {
+    /// Counts `mov`, which has just been made to reach `pos`
+    fn record(&mut self, mov: Move, pos: &Position, attacks: &Attacks) {
+        self.nodes += 1;
+        self.captures += mov.extract_capture() as u64;
+        self.en_passants += mov.extract_en_passant() as u64;
+        self.castles += mov.extract_castling() as u64;
+        self.promotions += (mov.extract_promoted_piece() != Piece::None) as u64;
+
+        let checkers = pos.checkers();
+        if checkers.is_empty() {
+            return;
+        }
+        self.checks += 1;
+        // A castling move checks with the rook on its destination next to the king's
+        let target_square = mov.extract_target();
+        let mut moved = BitBoard::empty();
+        moved.set_bit(if mov.extract_castling() {
+            let kingside = target_square as u8 & 0b111 == 6;
+            Square::from_u8_unchecked((target_square as u8 & !0b111) + if kingside { 5 } else { 3 })
+        } else {
+            target_square
+        });
+        if checkers.more_than_one() {
+            self.double_checks += 1;
+        } else if (checkers & !moved).is_not_empty() {
+            self.discovered_checks += 1;
+        }
+        self.checkmates += move_gen::generate_legal_moves(attacks, pos).is_empty() as u64;
+    }
+}
+
+impl AddAssign for PerftStats {
+    fn add_assign(&mut self, other: PerftStats) {
+        self.nodes += other.nodes;
+        self.captures += other.captures;
+        self.en_passants += other.en_passants;
+        self.castles += other.castles;
+        self.promotions += other.promotions;
+        self.checks += other.checks;
+        self.discovered_checks += other.discovered_checks;
+        self.double_checks += other.double_checks;
+        self.checkmates += other.checkmates;
+    }
+}
+
+impl Display for PerftStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "nodes: {}  captures: {}  e.p.: {}  castles: {}  promotions: {}  checks: {}  \
+             discovered checks: {}  double checks: {}  checkmates: {}",
+            self.nodes,
+            self.captures,
+            self.en_passants,
+            self.castles,
+            self.promotions,
+            self.checks,
+            self.discovered_checks,
+            self.double_checks,
+            self.checkmates
+        )
+    }
+}
+
 struct Perft {
     pos: Position,
     pub nodes: u64,
@@ -40,18 +326,322 @@ impl Perft {
 
     #[inline]
     fn perft_driver(&mut self, attacks: &Attacks, depth: u32) {
+        if depth == 0 {
+            self.nodes += 1;
+            return;
+        }
+        let moves = move_gen::generate_legal_moves(attacks, &self.pos);
+        for mov in moves {
+            let undo = self.pos.make_move_unchecked(mov);
+            self.perft_driver(attacks, depth - 1);
+            self.pos.unmake_move(mov, undo);
+        }
+    }
+
+    /// Bulk counting driver, returning the nodes below the position instead of adding to
+    /// `nodes` so that subtrees can be stored in `table`
+    fn perft_driver_bulk(
+        &mut self,
+        attacks: &Attacks,
+        depth: u32,
+        table: Option<&PerftTable>,
+    ) -> u64 {
+        if depth == 0 {
+            return 1;
+        }
+        if depth > 1 {
+            if let Some(nodes) = table.and_then(|table| table.get(self.pos.hash, depth)) {
+                return nodes;
+            }
+        }
+        let moves = move_gen::generate_legal_moves(attacks, &self.pos);
+        if depth == 1 {
+            return moves.len() as u64;
+        }
+        let mut nodes = 0;
+        for mov in moves {
+            let undo = self.pos.make_move_unchecked(mov);
+            nodes += self.perft_driver_bulk(attacks, depth - 1, table);
+            self.pos.unmake_move(mov, undo);
+        }
+        if let Some(table) = table {
+            table.set(self.pos.hash, depth, nodes);
+        }
+        nodes
+    }
+
+    /// Make/unmake driver that classifies every move into a leaf. `depth` must be at least 1.
+    fn perft_driver_stats(&mut self, attacks: &Attacks, depth: u32, stats: &mut PerftStats) {
+        let moves = move_gen::generate_legal_moves(attacks, &self.pos);
+        for mov in moves {
+            let undo = self.pos.make_move_unchecked(mov);
+            if depth == 1 {
+                stats.record(mov, &self.pos, attacks);
+            } else {
+                self.perft_driver_stats(attacks, depth - 1, stats);
+            }
+            self.pos.unmake_move(mov, undo);
+        }
+    }
+
+    /// Copy-make driver over the pseudo legal generator, which also cross-checks the legal one
+    #[inline]
+    fn perft_driver_copy_make(&mut self, attacks: &Attacks, depth: u32) {
         if depth == 0 {
             self.nodes += 1;
             return;
         }
         let moves = move_gen::generate_moves(attacks, &self.pos);
-        for mov in moves.moves.iter() {
+        for mov in moves {
             let copy = self.pos.clone();
-            if !self.pos.make_move(*mov, &attacks) {
+            if !self.pos.make_move(mov, attacks) {
                 continue;
             }
-            self.perft_driver(attacks, depth - 1);
+            self.perft_driver_copy_make(attacks, depth - 1);
             let _ = mem::replace(&mut self.pos, copy);
         }
     }
 }
+
+/// Always-replace table of subtree node counts keyed by Zobrist hash and depth, shared between
+/// threads without locking. Each entry stores its key xor'd with its count, so an entry torn by
+/// two threads writing at once fails the key check and is treated as a miss.
+struct PerftTable {
+    entries: Vec<(AtomicU64, AtomicU64)>,
+}
+
+impl PerftTable {
+    fn new(mb: usize) -> PerftTable {
+        let len = (mb << 20) / mem::size_of::<(AtomicU64, AtomicU64)>();
+        PerftTable {
+            entries: (0..len.max(1))
+                .map(|_| (AtomicU64::new(0), AtomicU64::new(0)))
+                .collect(),
+        }
+    }
+
+    /// Mixes the depth into the hash, so that the same position at different depths does not
+    /// share an entry
+    #[inline]
+    fn key(hash: u64, depth: u32) -> u64 {
+        hash ^ (depth as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15)
+    }
+
+    #[inline]
+    fn entry(&self, key: u64) -> &(AtomicU64, AtomicU64) {
+        &self.entries[((key as u128 * self.entries.len() as u128) >> 64) as usize]
+    }
+
+    #[inline]
+    fn get(&self, hash: u64, depth: u32) -> Option<u64> {
+        let key = PerftTable::key(hash, depth);
+        let (check, nodes) = self.entry(key);
+        let nodes = nodes.load(Ordering::Relaxed);
+        // An empty entry only matches a key of 0 with a count of 0, which is never stored
+        if check.load(Ordering::Relaxed) ^ nodes == key && nodes != 0 {
+            Some(nodes)
+        } else {
+            None
+        }
+    }
+
+    #[inline]
+    fn set(&self, hash: u64, depth: u32, nodes: u64) {
+        let key = PerftTable::key(hash, depth);
+        let (check, entry_nodes) = self.entry(key);
+        check.store(key ^ nodes, Ordering::Relaxed);
+        entry_nodes.store(nodes, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        attacks::ATTACKS,
+        utils::fen::{parse_fen, START_POSITION, TRICKY_POSITION},
+    };
+
+    use super::{
+        perft, perft_divide, perft_divide_stats, perft_fast, perft_stats, Perft, PerftOptions,
+        PerftStats,
+    };
+
+    const PERFT_POSITIONS: [(&str, u32, u64); 5] = [
+        (START_POSITION, 3, 8902),
+        (TRICKY_POSITION, 3, 97862),
+        ("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1", 4, 43238),
+        (
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+            3,
+            9467,
+        ),
+        (
+            "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+            3,
+            62379,
+        ),
+    ];
+
+    /// Chess960 positions with published perft results, in Shredder-FEN
+    const CHESS960_POSITIONS: [(&str, u32, u64); 6] = [
+        (
+            "bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w HFhf - 2 9",
+            3,
+            12189,
+        ),
+        (
+            "2nnrbkr/p1qppppp/8/1ppb4/6PP/3PP3/PPP2P2/BQNNRBKR w HEhe - 1 9",
+            3,
+            18002,
+        ),
+        (
+            "b1q1rrkb/pppppppp/3nn3/8/P7/1PPP4/4PPPP/BQNNRKRB w GE - 1 9",
+            3,
+            10471,
+        ),
+        (
+            "qbbnnrkr/2pp2pp/p7/1p2pp2/8/P3PP2/1PPP1KPP/QBBNNR1R w hf - 0 9",
+            3,
+            13440,
+        ),
+        (
+            "1nbbnrkr/p1p1ppp1/3p4/1p3P1p/3Pq2P/8/PPP1P1P1/QNBBNRKR w HFhf - 0 9",
+            3,
+            31058,
+        ),
+        (
+            "qnbnr1kr/ppp1b1pp/4p3/3p1p2/8/2NPP3/PPP1BPPP/QNB1R1KR w HEhe - 1 9",
+            3,
+            26578,
+        ),
+    ];
+
+    #[test]
+    fn test_perft() {
+        for (fen, depth, nodes) in PERFT_POSITIONS {
+            assert_eq!(
+                perft(parse_fen(fen).unwrap(), &ATTACKS, depth),
+                nodes,
+                "{}",
+                fen
+            );
+        }
+    }
+
+    #[test]
+    fn test_perft_copy_make() {
+        for (fen, depth, nodes) in PERFT_POSITIONS {
+            let mut p = Perft::new(parse_fen(fen).unwrap());
+            p.perft_driver_copy_make(&ATTACKS, depth);
+            assert_eq!(p.nodes, nodes, "{}", fen);
+        }
+    }
+
+    #[test]
+    fn test_perft_chess960() {
+        for (fen, depth, nodes) in CHESS960_POSITIONS {
+            assert_eq!(
+                perft(parse_fen(fen).unwrap(), &ATTACKS, depth),
+                nodes,
+                "{}",
+                fen
+            );
+        }
+    }
+    #[test]
+    fn test_perft_fast() {
+        let settings = [(1, 0), (1, 1), (4, 0), (4, 1)];
+        for (fen, depth, nodes) in PERFT_POSITIONS.iter().chain(CHESS960_POSITIONS.iter()) {
+            for &(threads, hash_mb) in settings.iter() {
+                let options = PerftOptions { threads, hash_mb };
+                let pos = parse_fen(fen).unwrap();
+                assert_eq!(perft_fast(&pos, *depth, &options), *nodes, "{}", fen);
+            }
+        }
+    }
+
+    #[test]
+    fn test_perft_divide_matches_serial() {
+        let pos = parse_fen(TRICKY_POSITION).unwrap();
+        let options = PerftOptions {
+            threads: 3,
+            hash_mb: 1,
+        };
+        assert_eq!(perft_fast(&pos, 4, &options), 4085603);
+        let divide = perft_divide(&pos, 3, &options);
+        assert_eq!(divide.len(), 48);
+        for (mov, nodes) in divide {
+            let mut child = pos.clone();
+            child.make_move_unchecked(mov);
+            assert_eq!(perft(child, &ATTACKS, 2), nodes, "{}", mov);
+        }
+        assert_eq!(perft_fast(&pos, 0, &options), 1);
+        assert!(perft_divide(&pos, 0, &options).is_empty());
+    }
+    #[test]
+    fn test_perft_stats() {
+        // Nodes, captures, e.p., castles, promotions, checks, discovered checks, double checks and
+        // checkmates from the Chess Programming Wiki
+        let cases = [
+            (START_POSITION, 4, [197281, 1576, 0, 0, 0, 469, 0, 0, 8]),
+            (
+                TRICKY_POSITION,
+                3,
+                [97862, 17102, 45, 3162, 0, 993, 0, 0, 1],
+            ),
+            (
+                "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+                4,
+                [43238, 3348, 123, 0, 0, 1680, 106, 0, 17],
+            ),
+            (
+                "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+                5,
+                [674624, 52051, 1165, 0, 0, 52950, 1292, 3, 0],
+            ),
+            (
+                "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+                3,
+                [9467, 1021, 4, 0, 120, 38, 2, 0, 22],
+            ),
+        ];
+        let options = PerftOptions {
+            threads: 2,
+            hash_mb: 0,
+        };
+        for (fen, depth, counts) in cases.iter() {
+            let expected = PerftStats {
+                nodes: counts[0],
+                captures: counts[1],
+                en_passants: counts[2],
+                castles: counts[3],
+                promotions: counts[4],
+                checks: counts[5],
+                discovered_checks: counts[6],
+                double_checks: counts[7],
+                checkmates: counts[8],
+            };
+            let pos = parse_fen(fen).unwrap();
+            assert_eq!(perft_stats(&pos, *depth, &options), expected, "{}", fen);
+        }
+
+        // The root moves are the leaves at depth 1
+        let pos = parse_fen(TRICKY_POSITION).unwrap();
+        let divide = perft_divide_stats(&pos, 1, &options);
+        assert_eq!(divide.len(), 48);
+        let castles: Vec<_> = divide
+            .iter()
+            .filter(|(_, stats)| stats.castles == 1)
+            .collect();
+        assert_eq!(castles.len(), 2);
+
+        // Castling queenside checks with the rook, which is not a discovered check
+        let pos = parse_fen("3k4/8/8/8/8/8/8/R3K3 w Q - 0 1").unwrap();
+        let divide = perft_divide_stats(&pos, 1, &options);
+        let (mov, stats) = divide.iter().find(|(_, stats)| stats.castles == 1).unwrap();
+        assert_eq!(mov.to_string(), "e1c1");
+        assert_eq!((stats.checks, stats.discovered_checks), (1, 0));
+        assert_eq!(perft_stats(&pos, 1, &options).discovered_checks, 0);
+        assert_eq!(perft_stats(&pos, 0, &options).nodes, 1);
+    }
+}
@@ -1,16 +1,19 @@
 use std::{convert::TryFrom, fmt::Display, mem};
 
 use crate::{
-    attacks::Attacks,
+    attacks::{Attacks, ATTACKS},
     bitboard::BitBoard,
     castle_rights::CastleRights,
-    move_list::Move,
-    piece::{Piece, BLACK_PIECES, WHITE_PIECES},
+    move_gen::generate_legal_moves,
+    move_list::{Move, MoveList},
+    piece::{Piece, BLACK_PIECES, PIECES, WHITE_PIECES},
     side::Side,
     square::Square,
+    utils::fen::Fen,
+    zobrist::ZOBRIST,
 };
 
-#[derive(Clone)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct Position {
     pub w_pawns: BitBoard,
     pub w_knights: BitBoard,
@@ -28,17 +31,118 @@ pub struct Position {
     pub w_occupancies: BitBoard,
     pub b_occupancies: BitBoard,
     pub all_occupancies: BitBoard,
+    /// Piece on each square, kept in sync with the piece bitboards
+    pub mailbox: [Option<Piece>; 64],
 
     pub side: Side,
     pub en_passant: Option<Square>,
     pub castle: CastleRights,
+    /// Halfmoves since the last pawn move or capture
+    pub halfmove_clock: u16,
+    /// Starts at 1 and is incremented after each black move
+    pub fullmove_number: u16,
+
+    /// Zobrist hash key, updated incrementally by `make_move`
+    pub hash: u64,
+}
+
+/// A rule broken by a position that cannot arise in a legal game. The engine is only guaranteed
+/// not to panic on positions without any.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Violation {
+    /// A side does not have exactly one king
+    KingCount(Side, u8),
+    /// A pawn on the first or eighth rank
+    PawnOnBackRank(Square),
+    /// A side has more than eight pawns, or more pieces beyond the starting set than it has
+    /// missing pawns to have promoted
+    Material(Side),
+    /// A castling right (as a FEN character) without the king and rook on their starting squares
+    CastleRight(char),
+    /// The en passant square is not behind a pawn that just made a double push
+    EnPassant(Square),
+    /// The side not to move is in check
+    OpponentInCheck,
+}
+
+impl Display for Violation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Violation::KingCount(side, count) => write!(f, "{} has {} kings", side, count),
+            Violation::PawnOnBackRank(square) => write!(f, "pawn on back rank at {}", square),
+            Violation::Material(side) => {
+                write!(f, "{} has more material than promotions allow", side)
+            }
+            Violation::CastleRight(right) => {
+                write!(f, "castling right '{}' without king and rook", right)
+            }
+            Violation::EnPassant(square) => {
+                write!(
+                    f,
+                    "en passant square {} without a double pushed pawn",
+                    square
+                )
+            }
+            Violation::OpponentInCheck => write!(f, "side not to move is in check"),
+        }
+    }
+}
+
+/// State that cannot be recovered from a move when unmaking it
+#[derive(Clone, Copy, Debug)]
+pub struct Undo {
+    pub captured: Piece,
+    pub castle: CastleRights,
+    pub en_passant: Option<Square>,
+    pub halfmove_clock: u16,
+    /// Kept as the counter stops at `u16::MAX` rather than overflowing
+    pub fullmove_number: u16,
+    pub hash: u64,
 }
 
 impl Position {
     /// Attempts to make a move. Reverts and returns false if the move is illegal. Returns true otherwise.
+    ///
+    /// This is the copy-make path, it clones the whole position to be able to revert. Use
+    /// `try_make_move` and `unmake_move` to avoid the copy.
     #[inline]
     pub fn make_move(&mut self, mov: Move, attacks: &Attacks) -> bool {
         let copy = self.clone();
+        self.make_move_unchecked(mov);
+
+        // If king attacked revert and return false
+        if self.is_king_attacked(self.side.opponent(), attacks) {
+            let _ = mem::replace(self, copy);
+            false
+        } else {
+            true
+        }
+    }
+
+    /// Attempts to make a move. Unmakes it and returns None if the move is illegal. Returns the
+    /// undo record needed by `unmake_move` otherwise.
+    #[inline]
+    pub fn try_make_move(&mut self, mov: Move, attacks: &Attacks) -> Option<Undo> {
+        let undo = self.make_move_unchecked(mov);
+        if self.is_king_attacked(self.side.opponent(), attacks) {
+            self.unmake_move(mov, undo);
+            None
+        } else {
+            Some(undo)
+        }
+    }
+
+    /// Makes a move without checking whether it leaves the king in check
+    #[inline]
+    pub fn make_move_unchecked(&mut self, mov: Move) -> Undo {
+        let mut undo = Undo {
+            captured: Piece::None,
+            castle: self.castle,
+            en_passant: self.en_passant,
+            halfmove_clock: self.halfmove_clock,
+            fullmove_number: self.fullmove_number,
+            hash: self.hash,
+        };
         // Parse move
         let source_square = mov.extract_source();
         let target_square = mov.extract_target();
@@ -49,30 +153,32 @@ impl Position {
         let en_passant = mov.extract_en_passant();
         let castling = mov.extract_castling();
 
-        // Move piece
-        self.get_piece_bitboard_mut(piece).pop_bit(source_square);
-        self.get_piece_bitboard_mut(piece).set_bit(target_square);
-
         // Handle captures
         if capture {
-            let opponent_pieces = if self.side == Side::White {
-                BLACK_PIECES
-            } else {
-                WHITE_PIECES
-            };
-            // Remove captured piece
-            for piece in opponent_pieces {
-                if self
-                    .get_piece_bitboard(piece)
-                    .get_bit(target_square)
-                    .is_not_empty()
-                {
-                    self.get_piece_bitboard_mut(piece).pop_bit(target_square);
-                    break;
-                }
+            // Remove captured piece (en passant targets are empty and handled below)
+            if let Some(captured) = self.piece_on(target_square) {
+                self.get_piece_bitboard_mut(captured).pop_bit(target_square);
+                self.hash ^= ZOBRIST.piece(captured, target_square);
+                undo.captured = captured;
             }
         }
 
+        // Lift the castling rook first, as in Chess960 the king may land on its square
+        let castling_rook = if castling {
+            let (rook, rook_source, rook_target) = self.castling_rook(target_square);
+            self.remove_piece(rook, rook_source);
+            Some((rook, rook_target))
+        } else {
+            None
+        };
+
+        // Move piece
+        self.get_piece_bitboard_mut(piece).pop_bit(source_square);
+        self.get_piece_bitboard_mut(piece).set_bit(target_square);
+        self.mailbox[source_square as usize] = None;
+        self.mailbox[target_square as usize] = Some(piece);
+        self.hash ^= ZOBRIST.piece(piece, source_square) ^ ZOBRIST.piece(piece, target_square);
+
         // Handle promotions
         if promoted_piece != Piece::None {
             // Remove pawn
@@ -80,21 +186,33 @@ impl Position {
             // Set promeoted piece
             self.get_piece_bitboard_mut(promoted_piece)
                 .set_bit(target_square);
+            self.mailbox[target_square as usize] = Some(promoted_piece);
+            self.hash ^=
+                ZOBRIST.piece(piece, target_square) ^ ZOBRIST.piece(promoted_piece, target_square);
         }
 
         // Handle en passant
         if en_passant {
-            match self.side {
-                Side::White => self
-                    .b_pawns
-                    .pop_bit(Square::from_u8_unchecked(target_square as u8 - 8)),
-                Side::Black => self
-                    .w_pawns
-                    .pop_bit(Square::from_u8_unchecked(target_square as u8 + 8)),
-            }
+            let (captured_pawn, captured_square) = match self.side {
+                Side::White => (
+                    Piece::BPawn,
+                    Square::from_u8_unchecked(target_square as u8 - 8),
+                ),
+                Side::Black => (
+                    Piece::WPawn,
+                    Square::from_u8_unchecked(target_square as u8 + 8),
+                ),
+            };
+            self.get_piece_bitboard_mut(captured_pawn)
+                .pop_bit(captured_square);
+            self.mailbox[captured_square as usize] = None;
+            self.hash ^= ZOBRIST.piece(captured_pawn, captured_square);
         }
 
         // Handle double pawn push
+        if let Some(square) = self.en_passant {
+            self.hash ^= ZOBRIST.en_passant(square);
+        }
         self.en_passant = if double_push {
             Some(match self.side {
                 Side::White => Square::from_u8_unchecked(target_square as u8 - 8),
@@ -103,42 +221,290 @@ impl Position {
         } else {
             None
         };
+        if let Some(square) = self.en_passant {
+            self.hash ^= ZOBRIST.en_passant(square);
+        }
 
         // Handle castling
-        if castling {
-            match target_square {
-                Square::C1 => {
-                    // White queenside
-                    // Move A1 rook
-                    self.w_rooks.pop_bit(Square::A1);
-                    self.w_rooks.set_bit(Square::D1);
-                }
-                Square::G1 => {
-                    // White kingside
-                    // Move H1 rook
-                    self.w_rooks.pop_bit(Square::H1);
-                    self.w_rooks.set_bit(Square::F1);
-                }
-                Square::C8 => {
-                    // Black queenside
-                    // Move A8 rook
-                    self.b_rooks.pop_bit(Square::A8);
-                    self.b_rooks.set_bit(Square::D8);
-                }
-                Square::G8 => {
-                    // Black kingnside
-                    // Move H8 rook
-                    self.b_rooks.pop_bit(Square::H8);
-                    self.b_rooks.set_bit(Square::F8);
-                }
-                invalid => panic!("'{}' is not a valid castling square", invalid),
-            }
+        if let Some((rook, rook_target)) = castling_rook {
+            self.put_piece(rook, rook_target);
         }
+
+        // Update move counters
+        if capture || piece == Piece::WPawn || piece == Piece::BPawn {
+            self.halfmove_clock = 0;
+        } else {
+            self.halfmove_clock = self.halfmove_clock.saturating_add(1);
+        }
+        if self.side == Side::Black {
+            self.fullmove_number = self.fullmove_number.saturating_add(1);
+        }
+
         // Update castle rights
+        self.hash ^= ZOBRIST.castle(self.castle);
         self.castle.update(source_square);
         self.castle.update(target_square);
+        self.hash ^= ZOBRIST.castle(self.castle);
 
         // Update occupancies
+        self.update_occupancies();
+
+        // Switch side
+        self.side = if self.side == Side::White {
+            Side::Black
+        } else {
+            Side::White
+        };
+        self.hash ^= ZOBRIST.side();
+
+        undo
+    }
+
+    /// Reverts a move made with `make_move_unchecked` or `try_make_move`
+    #[inline]
+    pub fn unmake_move(&mut self, mov: Move, undo: Undo) {
+        let source_square = mov.extract_source();
+        let target_square = mov.extract_target();
+        let piece = mov.extract_piece();
+        let promoted_piece = mov.extract_promoted_piece();
+
+        // Switch side back
+        self.side = self.side.opponent();
+
+        // Lift the castling rook first, as the king may have started on its target square
+        let castling_rook = if mov.extract_castling() {
+            let (rook, rook_source, rook_target) = self.castling_rook(target_square);
+            self.remove_piece(rook, rook_target);
+            Some((rook, rook_source))
+        } else {
+            None
+        };
+
+        // Move piece back
+        if promoted_piece != Piece::None {
+            self.get_piece_bitboard_mut(promoted_piece)
+                .pop_bit(target_square);
+        } else {
+            self.get_piece_bitboard_mut(piece).pop_bit(target_square);
+        }
+        self.get_piece_bitboard_mut(piece).set_bit(source_square);
+        self.mailbox[target_square as usize] = None;
+        self.mailbox[source_square as usize] = Some(piece);
+
+        // Restore captured piece
+        if undo.captured != Piece::None {
+            self.get_piece_bitboard_mut(undo.captured)
+                .set_bit(target_square);
+            self.mailbox[target_square as usize] = Some(undo.captured);
+        }
+
+        // Restore en passant captured pawn
+        if mov.extract_en_passant() {
+            let (captured_pawn, captured_square) = match self.side {
+                Side::White => (
+                    Piece::BPawn,
+                    Square::from_u8_unchecked(target_square as u8 - 8),
+                ),
+                Side::Black => (
+                    Piece::WPawn,
+                    Square::from_u8_unchecked(target_square as u8 + 8),
+                ),
+            };
+            self.get_piece_bitboard_mut(captured_pawn)
+                .set_bit(captured_square);
+            self.mailbox[captured_square as usize] = Some(captured_pawn);
+        }
+
+        // Move castling rook back
+        if let Some((rook, rook_source)) = castling_rook {
+            self.put_piece(rook, rook_source);
+        }
+
+        self.update_occupancies();
+
+        self.castle = undo.castle;
+        self.en_passant = undo.en_passant;
+        self.halfmove_clock = undo.halfmove_clock;
+        self.fullmove_number = undo.fullmove_number;
+        self.hash = undo.hash;
+    }
+
+    /// Returns the rook of the side to move and the squares it moves between when the king
+    /// castles to `king_target`
+    #[inline]
+    fn castling_rook(&self, king_target: Square) -> (Piece, Square, Square) {
+        let kingside = king_target as u8 & 0b111 == 6;
+        let rook = match self.side {
+            Side::White => Piece::WRook,
+            Side::Black => Piece::BRook,
+        };
+        let rook_source = self.castle.rook_square(self.side, kingside);
+        let rook_target =
+            Square::from_u8_unchecked((king_target as u8 & !0b111) + if kingside { 5 } else { 3 });
+        (rook, rook_source, rook_target)
+    }
+
+    /// Removes a piece, also used when unmaking where the hash is restored afterwards
+    #[inline]
+    fn remove_piece(&mut self, piece: Piece, square: Square) {
+        self.get_piece_bitboard_mut(piece).pop_bit(square);
+        self.mailbox[square as usize] = None;
+        self.hash ^= ZOBRIST.piece(piece, square);
+    }
+
+    /// Places a piece on an empty square
+    #[inline]
+    fn put_piece(&mut self, piece: Piece, square: Square) {
+        self.get_piece_bitboard_mut(piece).set_bit(square);
+        self.mailbox[square as usize] = Some(piece);
+        self.hash ^= ZOBRIST.piece(piece, square);
+    }
+
+    /// Returns the piece on a square
+    #[inline]
+    pub const fn piece_on(&self, square: Square) -> Option<Piece> {
+        self.mailbox[square as usize]
+    }
+
+    /// Checks the position for rules that cannot be broken in a legal game. Returns every
+    /// violation found, an empty list means the position is safe to search.
+    pub fn validate(&self, attacks: &Attacks) -> Vec<Violation> {
+        let mut violations = Vec::new();
+
+        let w_kings = self.w_king.count_bits();
+        let b_kings = self.b_king.count_bits();
+        if w_kings != 1 {
+            violations.push(Violation::KingCount(Side::White, w_kings));
+        }
+        if b_kings != 1 {
+            violations.push(Violation::KingCount(Side::Black, b_kings));
+        }
+
+        // First and eighth ranks
+        let back_rank_pawns = (self.w_pawns | self.b_pawns) & BitBoard(0xff00_0000_0000_00ff);
+        for square in back_rank_pawns {
+            violations.push(Violation::PawnOnBackRank(square));
+        }
+
+        // Knights, bishops, rooks and queens of the starting set, any more must be promoted pawns
+        for (side, pieces) in [(Side::White, WHITE_PIECES), (Side::Black, BLACK_PIECES)] {
+            let pawns = self.get_piece_bitboard(pieces[0]).count_bits();
+            let promoted: u8 = pieces[1..5]
+                .iter()
+                .zip([2, 2, 2, 1])
+                .map(|(&piece, start)| {
+                    self.get_piece_bitboard(piece)
+                        .count_bits()
+                        .saturating_sub(start)
+                })
+                .sum();
+            if pawns + promoted > 8 {
+                violations.push(Violation::Material(side));
+            }
+        }
+
+        let castle_rights = [
+            (Side::White, true, 'K', self.w_king, self.w_rooks),
+            (Side::White, false, 'Q', self.w_king, self.w_rooks),
+            (Side::Black, true, 'k', self.b_king, self.b_rooks),
+            (Side::Black, false, 'q', self.b_king, self.b_rooks),
+        ];
+        for (side, kingside, ch, king, rooks) in castle_rights {
+            if self.castle.has(side, kingside)
+                && (king.get_bit(self.castle.king_square(side)).is_empty()
+                    || rooks
+                        .get_bit(self.castle.rook_square(side, kingside))
+                        .is_empty())
+            {
+                violations.push(Violation::CastleRight(ch));
+            }
+        }
+
+        if let Some(square) = self.en_passant {
+            // The square must be on the sixth rank with white to move and the third with black,
+            // and the pawn that double pushed must be in front of it, with the square it came
+            // from empty
+            let (rank, pawns, pawn_square, origin_square) = match self.side {
+                Side::White => (
+                    5,
+                    self.b_pawns,
+                    Square::from_u8((square as u8).wrapping_sub(8)),
+                    Square::from_u8(square as u8 + 8),
+                ),
+                Side::Black => (
+                    2,
+                    self.w_pawns,
+                    Square::from_u8(square as u8 + 8),
+                    Square::from_u8((square as u8).wrapping_sub(8)),
+                ),
+            };
+            let valid = match (pawn_square, origin_square) {
+                (Some(pawn_square), Some(origin_square)) => {
+                    square as u8 / 8 == rank
+                        && pawns.get_bit(pawn_square).is_not_empty()
+                        && self.piece_on(square).is_none()
+                        && self.piece_on(origin_square).is_none()
+                }
+                _ => false,
+            };
+            if !valid {
+                violations.push(Violation::EnPassant(square));
+            }
+        }
+
+        if w_kings == 1 && b_kings == 1 && self.is_king_attacked(self.side.opponent(), attacks) {
+            violations.push(Violation::OpponentInCheck);
+        }
+
+        violations
+    }
+
+    /// Returns true if the side to move is in check
+    #[inline]
+    pub fn in_check(&self, attacks: &Attacks) -> bool {
+        self.is_king_attacked(self.side, attacks)
+    }
+
+    /// `in_check` using the global `ATTACKS` tables
+    #[inline]
+    pub fn is_check(&self) -> bool {
+        self.in_check(&ATTACKS)
+    }
+
+    /// Pieces of the side not to move that give check
+    #[inline]
+    pub fn checkers(&self) -> BitBoard {
+        let king = match self.side {
+            Side::White => self.w_king,
+            Side::Black => self.b_king,
+        };
+        ATTACKS.attackers_to(self, king.get_lsb_square().unwrap(), self.all_occupancies)
+            & self.get_occupancy_bitboard(self.side.opponent())
+    }
+
+    /// The legal moves in this position, using the global `ATTACKS` tables
+    #[inline]
+    pub fn legal_moves(&self) -> MoveList {
+        generate_legal_moves(&ATTACKS, self)
+    }
+
+    /// `make_move` using the global `ATTACKS` tables
+    #[inline]
+    pub fn play(&mut self, mov: Move) -> bool {
+        self.make_move(mov, &ATTACKS)
+    }
+
+    #[inline]
+    pub(crate) fn is_king_attacked(&self, side: Side, attacks: &Attacks) -> bool {
+        let king = match side {
+            Side::White => self.w_king,
+            Side::Black => self.b_king,
+        };
+        attacks.is_square_attacked(self, king.get_lsb_square().unwrap(), side.opponent())
+    }
+
+    #[inline]
+    fn update_occupancies(&mut self) {
         self.w_occupancies = self.w_pawns
             | self.w_knights
             | self.w_bishops
@@ -152,29 +518,6 @@ impl Position {
             | self.b_queens
             | self.b_king;
         self.all_occupancies = self.w_occupancies | self.b_occupancies;
-
-        // Switch side
-        self.side = if self.side == Side::White {
-            Side::Black
-        } else {
-            Side::White
-        };
-
-        // If king attacked revert and return false
-        if (self.side == Side::White
-            && attacks.is_square_attacked(&self, self.b_king.get_lsb_square().unwrap(), self.side))
-            || (self.side == Side::Black
-                && attacks.is_square_attacked(
-                    &self,
-                    self.w_king.get_lsb_square().unwrap(),
-                    self.side,
-                ))
-        {
-            let _ = mem::replace(self, copy);
-            false
-        } else {
-            true
-        }
     }
 
     #[inline]
@@ -186,6 +529,40 @@ impl Position {
         }
     }
 
+    /// Returns a formatter that writes the position as a FEN string
+    #[inline]
+    pub fn fen(&self) -> Fen<'_> {
+        Fen(self)
+    }
+
+    pub fn to_fen(&self) -> String {
+        self.fen().to_string()
+    }
+
+    /// Returns true if fifty moves have been made by each side without a pawn move or capture
+    #[inline]
+    pub const fn is_fifty_move_draw(&self) -> bool {
+        self.halfmove_clock >= 100
+    }
+
+    /// Computes the Zobrist hash of the position from scratch
+    pub fn gen_hash(&self) -> u64 {
+        let mut hash = 0;
+        for piece in PIECES {
+            for square in self.get_piece_bitboard(piece) {
+                hash ^= ZOBRIST.piece(piece, square);
+            }
+        }
+        if let Some(square) = self.en_passant {
+            hash ^= ZOBRIST.en_passant(square);
+        }
+        hash ^= ZOBRIST.castle(self.castle);
+        if self.side == Side::Black {
+            hash ^= ZOBRIST.side();
+        }
+        hash
+    }
+
     #[inline]
     pub fn get_piece_bitboard(&self, piece: Piece) -> BitBoard {
         match piece {
@@ -245,34 +622,9 @@ impl Position {
         for r in (0..8).rev() {
             print!(" {}", r + 1);
             for f in 0..8 {
-                let b = BitBoard::from(Square::from_fr_unchecked(f, r));
-                let piece = if (self.w_pawns & b).is_not_empty() {
-                    Piece::WPawn
-                } else if (self.w_knights & b).is_not_empty() {
-                    Piece::WKnight
-                } else if (self.w_bishops & b).is_not_empty() {
-                    Piece::WBishop
-                } else if (self.w_rooks & b).is_not_empty() {
-                    Piece::WRook
-                } else if (self.w_queens & b).is_not_empty() {
-                    Piece::WQueen
-                } else if (self.w_king & b).is_not_empty() {
-                    Piece::WKing
-                } else if (self.b_pawns & b).is_not_empty() {
-                    Piece::BPawn
-                } else if (self.b_knights & b).is_not_empty() {
-                    Piece::BKnight
-                } else if (self.b_bishops & b).is_not_empty() {
-                    Piece::BBishop
-                } else if (self.b_rooks & b).is_not_empty() {
-                    Piece::BRook
-                } else if (self.b_queens & b).is_not_empty() {
-                    Piece::BQueen
-                } else if (self.b_king & b).is_not_empty() {
-                    Piece::BKing
-                } else {
-                    Piece::None
-                };
+                let piece = self
+                    .piece_on(Square::from_fr_unchecked(f, r))
+                    .unwrap_or(Piece::None);
 
                 print!(" {}", piece.to_unicode());
             }
@@ -286,6 +638,8 @@ impl Position {
         };
         println!(" En passant square: {}", en_passant);
         println!(" Castling rights:   {}", self.castle);
+        println!(" Halfmove clock:    {}", self.halfmove_clock);
+        println!(" Fullmove number:   {}", self.fullmove_number);
     }
 }
 
@@ -294,33 +648,9 @@ impl Display for Position {
         let mut bstr = String::with_capacity(72);
         for r in (0..8).rev() {
             for c in 0..8 {
-                let b = BitBoard::from(Square::try_from((r, c)).unwrap());
-                if (self.w_pawns & b).is_not_empty() {
-                    bstr.push('P');
-                } else if (self.w_knights & b).is_not_empty() {
-                    bstr.push('N');
-                } else if (self.w_bishops & b).is_not_empty() {
-                    bstr.push('B');
-                } else if (self.w_rooks & b).is_not_empty() {
-                    bstr.push('R');
-                } else if (self.w_queens & b).is_not_empty() {
-                    bstr.push('Q');
-                } else if (self.w_king & b).is_not_empty() {
-                    bstr.push('K');
-                } else if (self.b_pawns & b).is_not_empty() {
-                    bstr.push('p');
-                } else if (self.b_knights & b).is_not_empty() {
-                    bstr.push('n');
-                } else if (self.b_bishops & b).is_not_empty() {
-                    bstr.push('b');
-                } else if (self.b_rooks & b).is_not_empty() {
-                    bstr.push('r');
-                } else if (self.b_queens & b).is_not_empty() {
-                    bstr.push('q');
-                } else if (self.b_king & b).is_not_empty() {
-                    bstr.push('k');
-                } else {
-                    bstr.push('_');
+                match self.piece_on(Square::try_from((r, c)).unwrap()) {
+                    Some(piece) => bstr.push(piece.to_ascii()),
+                    None => bstr.push('_'),
                 }
                 if c == 7 {
                     bstr.push('\n')
@@ -332,3 +662,351 @@ impl Display for Position {
         write!(f, "{}", bstr)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        attacks::{Attacks, ATTACKS},
+        move_gen::generate_moves,
+        perft::perft,
+        piece::{Piece, PIECES},
+        search::Search,
+        side::Side,
+        square::{Square, SQUARES},
+        transposition::TranspositionTable,
+        utils::{
+            fen::{parse_fen, CMK_POSITION, KILLER_POSITION, START_POSITION, TRICKY_POSITION},
+            random::Random,
+        },
+    };
+
+    use super::{Position, Violation};
+
+    /// Chess960 positions where the king starts on its castling destination, and where a rook
+    /// starts on the king's destination
+    const CHESS960_POSITIONS: [&str; 2] = [
+        "bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w HFhf - 2 9",
+        "b1q1rrkb/pppppppp/3nn3/8/P7/1PPP4/4PPPP/BQNNRKRB w GE - 1 9",
+    ];
+
+    fn check_hash(pos: &Position, attacks: &Attacks, depth: u32) {
+        assert_eq!(pos.hash, pos.gen_hash(), "hash mismatch in {}", pos);
+        if depth == 0 {
+            return;
+        }
+        for mov in generate_moves(attacks, pos) {
+            let mut copy = pos.clone();
+            if copy.make_move(mov, attacks) {
+                check_hash(&copy, attacks, depth - 1);
+            }
+        }
+    }
+
+    fn check_mailbox(pos: &mut Position, attacks: &Attacks, depth: u32) {
+        for square in SQUARES {
+            let piece = PIECES
+                .iter()
+                .copied()
+                .find(|&p| pos.get_piece_bitboard(p).get_bit(square).is_not_empty());
+            assert_eq!(
+                pos.piece_on(square),
+                piece,
+                "{} in {}",
+                square,
+                pos.to_fen()
+            );
+        }
+        if depth == 0 {
+            return;
+        }
+        for mov in generate_moves(attacks, pos) {
+            if let Some(undo) = pos.try_make_move(mov, attacks) {
+                check_mailbox(pos, attacks, depth - 1);
+                pos.unmake_move(mov, undo);
+            }
+        }
+    }
+
+    #[test]
+    fn test_incremental_hash() {
+        for fen in [
+            START_POSITION,
+            TRICKY_POSITION,
+            KILLER_POSITION,
+            CMK_POSITION,
+        ]
+        .iter()
+        .chain(CHESS960_POSITIONS.iter())
+        {
+            check_hash(&parse_fen(fen).unwrap(), &ATTACKS, 3);
+        }
+    }
+
+    #[test]
+    fn test_mailbox() {
+        for fen in [
+            START_POSITION,
+            TRICKY_POSITION,
+            KILLER_POSITION,
+            CMK_POSITION,
+        ]
+        .iter()
+        .chain(CHESS960_POSITIONS.iter())
+        {
+            check_mailbox(&mut parse_fen(fen).unwrap(), &ATTACKS, 3);
+        }
+        let pos = parse_fen(START_POSITION).unwrap();
+        assert_eq!(pos.piece_on(Square::E1), Some(Piece::WKing));
+        assert_eq!(pos.piece_on(Square::D8), Some(Piece::BQueen));
+        assert_eq!(pos.piece_on(Square::E4), None);
+    }
+
+    #[test]
+    fn test_hash_transposition() {
+        let a = parse_fen("rnbqkbnr/pppppppp/8/8/8/5N2/PPPPPPPP/RNBQKB1R b KQkq - 1 1").unwrap();
+        let b = parse_fen("rnbqkbnr/pppppppp/8/8/8/5N2/PPPPPPPP/RNBQKB1R w KQkq - 1 1").unwrap();
+        let c = parse_fen("rnbqkbnr/pppppppp/8/8/8/5N2/PPPPPPPP/RNBQKB1R b Kkq - 1 1").unwrap();
+        assert_ne!(a.hash, b.hash);
+        assert_ne!(a.hash, c.hash);
+
+        // Nf3 Nf6 Ng1 Ng8 returns to the start position
+        let mut pos = parse_fen(START_POSITION).unwrap();
+        let start_hash = pos.hash;
+        for mov in ["g1f3", "g8f6", "f3g1", "f6g8"] {
+            let moves = generate_moves(&ATTACKS, &pos);
+            let mov = moves.into_iter().find(|m| m.to_string() == mov).unwrap();
+            assert!(pos.make_move(mov, &ATTACKS));
+        }
+        assert_eq!(pos.hash, start_hash);
+    }
+
+    #[test]
+    fn test_move_counters() {
+        let mut pos = parse_fen(CMK_POSITION).unwrap();
+        assert_eq!(pos.halfmove_clock, 0);
+        assert_eq!(pos.fullmove_number, 9);
+
+        let play = |pos: &mut Position, mov: &str| {
+            let moves = generate_moves(&ATTACKS, pos);
+            let mov = moves.into_iter().find(|m| m.to_string() == mov).unwrap();
+            assert!(pos.make_move(mov, &ATTACKS));
+        };
+        // Quiet piece moves advance the clock
+        play(&mut pos, "d8e7");
+        assert_eq!((pos.halfmove_clock, pos.fullmove_number), (1, 10));
+        play(&mut pos, "c1g5");
+        assert_eq!((pos.halfmove_clock, pos.fullmove_number), (2, 10));
+        // Pawn moves reset it
+        play(&mut pos, "h7h6");
+        assert_eq!((pos.halfmove_clock, pos.fullmove_number), (0, 11));
+        play(&mut pos, "g2h1");
+        assert_eq!((pos.halfmove_clock, pos.fullmove_number), (1, 11));
+        // As do captures
+        play(&mut pos, "h6g5");
+        assert_eq!((pos.halfmove_clock, pos.fullmove_number), (0, 12));
+
+        // Counters at their largest stay there instead of overflowing
+        let mut pos = parse_fen("4k3/8/8/8/8/8/8/R3K3 b - - 65535 65535").unwrap();
+        let before = pos.clone();
+        let moves = generate_moves(&ATTACKS, &pos);
+        let mov = moves.into_iter().find(|m| m.to_string() == "e8d7").unwrap();
+        let undo = pos.make_move_unchecked(mov);
+        assert_eq!((pos.halfmove_clock, pos.fullmove_number), (65535, 65535));
+        pos.unmake_move(mov, undo);
+        assert_eq!(pos, before);
+    }
+
+    #[test]
+    fn test_fifty_move_draw() {
+        let mut pos = parse_fen("8/8/4k3/8/8/3K4/8/7R w - - 99 80").unwrap();
+        assert!(!pos.is_fifty_move_draw());
+        let moves = generate_moves(&ATTACKS, &pos);
+        let mov = moves.into_iter().find(|m| m.to_string() == "h1h2").unwrap();
+        assert!(pos.make_move(mov, &ATTACKS));
+        assert!(pos.is_fifty_move_draw());
+
+        // Missing counters default to the start of a game
+        let pos = parse_fen("8/8/4k3/8/8/3K4/8/7R w - -").unwrap();
+        assert_eq!((pos.halfmove_clock, pos.fullmove_number), (0, 1));
+        assert!(parse_fen("8/8/4k3/8/8/3K4/8/7R w - - x 1").is_err());
+    }
+
+    fn check_unmake(pos: &mut Position, attacks: &Attacks, depth: u32) {
+        if depth == 0 {
+            return;
+        }
+        for mov in generate_moves(attacks, pos) {
+            let before = pos.clone();
+            let undo = pos.make_move_unchecked(mov);
+            check_unmake(pos, attacks, depth - 1);
+            pos.unmake_move(mov, undo);
+            assert_eq!(*pos, before, "unmake {} failed in {}", mov, before.to_fen());
+        }
+    }
+
+    #[test]
+    fn test_unmake_move() {
+        for fen in [
+            START_POSITION,
+            TRICKY_POSITION,
+            KILLER_POSITION,
+            CMK_POSITION,
+        ]
+        .iter()
+        .chain(CHESS960_POSITIONS.iter())
+        {
+            check_unmake(&mut parse_fen(fen).unwrap(), &ATTACKS, 3);
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        for fen in [START_POSITION, TRICKY_POSITION, CMK_POSITION] {
+            assert!(parse_fen(fen).unwrap().validate(&ATTACKS).is_empty());
+        }
+
+        let cases = [
+            (
+                "8/8/8/8/8/8/8/4K3 w - - 0 1",
+                vec![Violation::KingCount(Side::Black, 0)],
+            ),
+            (
+                "k7/8/8/8/8/8/8/KK6 w - - 0 1",
+                vec![Violation::KingCount(Side::White, 2)],
+            ),
+            (
+                "P3k3/8/8/8/8/8/8/4K2p w - - 0 1",
+                vec![
+                    Violation::PawnOnBackRank(Square::H1),
+                    Violation::PawnOnBackRank(Square::A8),
+                ],
+            ),
+            // The well known test position has nine white pawns
+            (KILLER_POSITION, vec![Violation::Material(Side::White)]),
+            (
+                "4k3/pppppppp/8/8/8/8/PPPPPPPP/NNN1K3 w - - 0 1",
+                vec![Violation::Material(Side::White)],
+            ),
+            // Enough queens for more moves than a move list holds
+            (
+                "QQQQQQnK/Q5QQ/Q6Q/Q6Q/p6Q/QQ5Q/ppQ4Q/kbQQQQQQ w - - 0 1",
+                vec![Violation::Material(Side::White)],
+            ),
+            (
+                "r3k3/8/8/8/8/8/8/4K2R w KQkq - 0 1",
+                vec![Violation::CastleRight('Q'), Violation::CastleRight('k')],
+            ),
+            (
+                "4k3/8/8/8/8/8/8/4K3 w - e6 0 1",
+                vec![Violation::EnPassant(Square::E6)],
+            ),
+            // A pawn behind the square, but on the wrong rank for the side to move
+            (
+                "4k3/8/8/8/8/8/4p3/K7 w - e3 0 1",
+                vec![Violation::EnPassant(Square::E3)],
+            ),
+            (
+                "k7/4P3/8/8/8/8/8/4K3 b - e6 0 1",
+                vec![Violation::EnPassant(Square::E6)],
+            ),
+            (
+                "4k3/8/8/8/4R3/8/8/4K3 w - - 0 1",
+                vec![Violation::OpponentInCheck],
+            ),
+        ];
+        for (fen, violations) in cases {
+            assert_eq!(
+                parse_fen(fen).unwrap().validate(&ATTACKS),
+                violations,
+                "{}",
+                fen
+            );
+        }
+    }
+
+    #[test]
+    fn test_valid_positions_do_not_panic() {
+        let mut rand = Random::new();
+        let mut tt = TranspositionTable::new(1);
+        let pieces = [
+            Piece::WPawn,
+            Piece::WKnight,
+            Piece::WBishop,
+            Piece::WRook,
+            Piece::WQueen,
+            Piece::BPawn,
+            Piece::BKnight,
+            Piece::BBishop,
+            Piece::BRook,
+            Piece::BQueen,
+        ];
+        let mut searched = 0;
+        while searched < 50 {
+            // Random kings and pieces, side to move and castling rights
+            let mut board = [None; 64];
+            board[(rand.rand_u32() % 64) as usize] = Some(Piece::WKing);
+            board[(rand.rand_u32() % 64) as usize] = Some(Piece::BKing);
+            // Every other position is crowded, to reach the limits on material
+            let count = if searched % 2 == 0 { 12 } else { 40 };
+            for _ in 0..rand.rand_u32() % count {
+                let piece = pieces[(rand.rand_u32() % 10) as usize];
+                board[(rand.rand_u32() % 64) as usize] = Some(piece);
+            }
+            let mut fen = String::new();
+            for rank in (0..8).rev() {
+                for file in 0..8 {
+                    match board[rank * 8 + file] {
+                        Some(piece) => fen.push(piece.to_ascii()),
+                        None => fen.push('1'),
+                    }
+                }
+                fen.push(if rank > 0 { '/' } else { ' ' });
+            }
+            fen.push_str(["w ", "b "][(rand.rand_u32() % 2) as usize]);
+            fen.push_str(["KQkq", "Kq", "-"][(rand.rand_u32() % 3) as usize]);
+            fen.push_str(" - 0 1");
+
+            let mut pos = parse_fen(&fen).unwrap();
+            if !pos.validate(&ATTACKS).is_empty() {
+                continue;
+            }
+            perft(pos.clone(), &ATTACKS, 2);
+            Search::new().negamax(&mut pos, &ATTACKS, &mut tt, -50000, 50000, 2);
+            searched += 1;
+        }
+    }
+
+    #[test]
+    fn test_global_attacks_api() {
+        let mut pos = parse_fen(START_POSITION).unwrap();
+        assert_eq!(pos.legal_moves().len(), 20);
+        assert!(!pos.is_check());
+        assert!(pos.checkers().is_empty());
+        for mov in ["f2f3", "e7e5", "g2g4", "d8h4"] {
+            let mov = pos
+                .legal_moves()
+                .into_iter()
+                .find(|m| m.to_string() == mov)
+                .unwrap();
+            assert!(pos.play(mov));
+        }
+        // Fool's mate
+        assert!(pos.is_check());
+        assert_eq!(pos.checkers().get_lsb_square(), Some(Square::H4));
+        assert_eq!(pos.legal_moves().len(), 0);
+
+        // Double check from a knight and a rook
+        let pos = parse_fen("4r1k1/8/8/8/8/3n4/8/4K3 w - - 0 1").unwrap();
+        assert_eq!(pos.checkers().count_bits(), 2);
+
+        // SAN, legality and exchange evaluation without passing the tables
+        let pos = parse_fen(TRICKY_POSITION).unwrap();
+        let mov = pos.parse_san("Bxa6").unwrap();
+        assert_eq!(pos.move_to_san(mov), "Bxa6");
+        assert!(pos.is_pseudo_legal(mov) && pos.is_legal(mov));
+        assert_eq!(pos.see(mov), 350);
+        assert!(pos.see_ge(mov, 350) && !pos.see_ge(mov, 351));
+        let stale = pos.parse_san("Nxf7").unwrap();
+        assert!(!parse_fen(START_POSITION).unwrap().is_pseudo_legal(stale));
+    }
+}
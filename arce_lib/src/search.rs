@@ -1,74 +1,317 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
 use crate::{
-    attacks::Attacks, evaluation::evaluate, move_gen::generate_moves, move_list::Move,
-    position::Position, side::Side,
+    attacks::Attacks,
+    evaluation::evaluate,
+    history::GameHistory,
+    move_gen::generate_legal_moves,
+    move_list::Move,
+    move_picker::MovePicker,
+    piece::Piece,
+    position::Position,
+    side::Side,
+    transposition::{Bound, TranspositionTable},
+    utils::uci::format_move,
 };
 
-pub fn search_pos(position: &mut Position, attacks: &Attacks, depth: u8) {
-    let mut search = Search::new();
-    let score = search.negamax(position, attacks, -50000, 50000, depth);
-    println!("info score cp {} nodes {}", score, search.nodes);
-    println!("bestmove {}", search.best_move);
+/// Deepest ply that killer moves and principal variations are kept for
+const MAX_PLY: usize = 128;
+
+/// Deepest iteration of an unlimited search
+pub const MAX_DEPTH: u8 = 64;
+
+/// Bound on every score, used as the initial alpha beta window
+pub const INFINITY: i32 = 50000;
+
+/// Score of being checkmated at the root. A mate `ply` moves from the root scores
+/// `-MATE_VALUE + ply`, so that faster mates are preferred.
+pub const MATE_VALUE: i32 = 49000;
+
+/// Scores beyond this are mates
+pub const MATE_SCORE: i32 = 48000;
+
+/// How many nodes are searched between checks of the time and node limits, a power of two
+const CHECK_INTERVAL: u64 = 2048;
+
+/// Limits on a search, from the UCI `go` command. The search stops at whichever limit it reaches
+/// first, and runs to `MAX_DEPTH` or until it is told to stop if there are none.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SearchLimits {
+    pub depth: Option<u8>,
+    pub nodes: Option<u64>,
+    pub movetime: Option<Duration>,
+}
+
+/// Searches the position with iterative deepening and returns the best move. An `info` line is
+/// printed after every completed iteration. Results in `tt` from earlier searches are reused.
+/// Setting `stop` from another thread ends the search early.
+pub fn search_pos(
+    position: &mut Position,
+    attacks: &Attacks,
+    tt: &mut TranspositionTable,
+    limits: &SearchLimits,
+    history: &GameHistory,
+    chess960: bool,
+    stop: Arc<AtomicBool>,
+) -> Move {
+    let mut search = Search::with_history(history.clone());
+    search.chess960 = chess960;
+    search.set_stop_flag(stop);
+    search.iterative_deepening(position, attacks, tt, limits)
 }
 
 pub struct Search {
-    nodes: u32,
+    nodes: u64,
     ply: i32,
+    /// Deepest ply reached in the current iteration
+    seldepth: i32,
     best_move: Move,
+    history: GameHistory,
+    /// Quiet moves that caused a beta cutoff, two per ply
+    killers: [[Move; 2]; MAX_PLY],
+    /// Triangular principal variation table. Row `ply` holds the best line found from that
+    /// ply, `pv_length[ply]` long.
+    pv_table: [[Move; MAX_PLY]; MAX_PLY],
+    pv_length: [usize; MAX_PLY],
+    limits: SearchLimits,
+    start_time: Instant,
+    /// Whether the limits are checked, which they are not during the first iteration
+    can_stop: bool,
+    /// Set once a limit is reached, after which every node returns straight away
+    stopped: bool,
+    /// Set from outside the search, such as by the UCI `stop` command, to stop it like a limit
+    stop: Arc<AtomicBool>,
+    /// Format castling in the principal variation as the king capturing its own rook
+    pub chess960: bool,
 }
 
 impl Search {
     pub fn new() -> Search {
+        Search::with_history(GameHistory::new())
+    }
+
+    /// Creates a search that treats positions from `history` as already played
+    pub fn with_history(history: GameHistory) -> Search {
         Search {
             nodes: 0,
             ply: 0,
+            seldepth: 0,
             best_move: Move::empty(),
+            history,
+            killers: [[Move::empty(); 2]; MAX_PLY],
+            pv_table: [[Move::empty(); MAX_PLY]; MAX_PLY],
+            pv_length: [0; MAX_PLY],
+            limits: SearchLimits::default(),
+            start_time: Instant::now(),
+            can_stop: false,
+            stopped: false,
+            stop: Arc::new(AtomicBool::new(false)),
+            chess960: false,
         }
     }
 
-    /// Negamax alpha beta search
+    /// Shares the flag that stops the search when set, which is checked along with the limits
+    pub fn set_stop_flag(&mut self, stop: Arc<AtomicBool>) {
+        self.stop = stop;
+    }
+
+    /// Searches depth 1, 2, 3 and so on until a limit is reached, printing an `info` line after
+    /// each iteration. Returns the best move of the last completed iteration, as an iteration
+    /// cut short by a limit is thrown away. The first iteration always completes, so there is a
+    /// move to play whenever the position has one.
+    pub fn iterative_deepening(
+        &mut self,
+        position: &mut Position,
+        attacks: &Attacks,
+        tt: &mut TranspositionTable,
+        limits: &SearchLimits,
+    ) -> Move {
+        tt.new_search();
+        self.limits = *limits;
+        self.start_time = Instant::now();
+        self.nodes = 0;
+        self.stopped = false;
+        let max_depth = limits.depth.unwrap_or(MAX_DEPTH).clamp(1, MAX_DEPTH);
+        let mut best_move = Move::empty();
+
+        for depth in 1..=max_depth {
+            self.can_stop = depth > 1;
+            self.seldepth = 0;
+            self.best_move = Move::empty();
+            let score = self.negamax(position, attacks, tt, -INFINITY, INFINITY, depth);
+            if self.stopped {
+                break;
+            }
+            best_move = self.best_move;
+            self.print_info(position, tt, depth, score);
+            // No legal moves, so deeper iterations would find nothing new
+            if self.pv_length[0] == 0 {
+                break;
+            }
+        }
+        best_move
+    }
+
+    /// Prints the UCI `info` line of a completed iteration
+    fn print_info(&self, position: &Position, tt: &TranspositionTable, depth: u8, score: i32) {
+        let elapsed = self.start_time.elapsed();
+        let score = if score.abs() > MATE_SCORE {
+            // Moves, rather than plies, to mate
+            let moves = (MATE_VALUE - score.abs() + 1) / 2;
+            format!("mate {}", if score > 0 { moves } else { -moves })
+        } else {
+            format!("cp {}", score)
+        };
+        let nps = (self.nodes as f64 / elapsed.as_secs_f64().max(0.001)) as u64;
+
+        let mut pv = String::new();
+        let mut pos = position.clone();
+        for &mov in &self.pv_table[0][..self.pv_length[0]] {
+            pv.push(' ');
+            pv.push_str(&format_move(mov, &pos, self.chess960));
+            pos.make_move_unchecked(mov);
+        }
+        println!(
+            "info depth {} seldepth {} score {} nodes {} nps {} time {} hashfull {} pv{}",
+            depth,
+            self.seldepth,
+            score,
+            self.nodes,
+            nps,
+            elapsed.as_millis(),
+            tt.hashfull(),
+            pv
+        );
+    }
+
+    /// The best line found by the last completed search
+    pub fn pv(&self) -> &[Move] {
+        &self.pv_table[0][..self.pv_length[0]]
+    }
+
+    /// Nodes searched since the search started
+    pub fn nodes(&self) -> u64 {
+        self.nodes
+    }
+
+    /// Checks the node and time limits and the stop flag, stopping the search if one has been
+    /// reached. The first iteration is never stopped.
+    #[inline]
+    fn check_limits(&mut self) {
+        if !self.can_stop || self.nodes & (CHECK_INTERVAL - 1) != 0 {
+            return;
+        }
+        let nodes_up = self.limits.nodes.is_some_and(|nodes| self.nodes >= nodes);
+        let time_up = self
+            .limits
+            .movetime
+            .is_some_and(|movetime| self.start_time.elapsed() >= movetime);
+        self.stopped = nodes_up || time_up || self.stop.load(Ordering::Relaxed);
+    }
+
+    /// Negamax alpha beta search. Positions already searched deep enough in `tt` are cut off
+    /// without searching them again, except at the root.
     #[inline]
     pub fn negamax(
         &mut self,
         position: &mut Position,
         attacks: &Attacks,
+        tt: &mut TranspositionTable,
         mut alpha: i32,
         beta: i32,
         depth: u8,
     ) -> i32 {
-        if depth == 0 {
+        self.nodes += 1;
+        self.pv_length[self.ply as usize] = self.ply as usize;
+        self.seldepth = self.seldepth.max(self.ply);
+        self.check_limits();
+        if self.stopped {
+            return 0;
+        }
+        // Fifty move rule and repetitions
+        if self.ply > 0 && self.history.is_repetition(position) {
+            return 0;
+        }
+        if self.ply > 0 && position.is_fifty_move_draw() {
+            // A checkmate on the move that reaches the limit still wins
+            if position.in_check(attacks) && generate_legal_moves(attacks, position).is_empty() {
+                return -MATE_VALUE + self.ply;
+            }
+            return 0;
+        }
+        if depth == 0 || self.ply as usize >= MAX_PLY - 1 {
             return evaluate(position);
         }
+
+        let entry = tt.probe(position.hash);
+        if let Some(entry) = entry.filter(|entry| self.ply > 0 && entry.depth >= depth) {
+            let score = entry.score(self.ply);
+            match entry.bound {
+                Bound::Exact => {
+                    // Keep the stored move as the start of the principal variation
+                    if position.is_legal_with(entry.best_move, attacks) {
+                        self.pv_length[self.ply as usize + 1] = self.ply as usize + 1;
+                        self.update_pv(entry.best_move);
+                    }
+                    return score;
+                }
+                Bound::Lower if score >= beta => return beta,
+                Bound::Upper if score <= alpha => return alpha,
+                _ => {}
+            }
+        }
         let (king_square, opp_side) = match position.side {
             Side::White => (position.w_king.get_lsb_square().unwrap(), Side::Black),
             Side::Black => (position.b_king.get_lsb_square().unwrap(), Side::White),
         };
 
-        let in_check = attacks.is_square_attacked(&position, king_square, opp_side);
-        let mut legal_moves = 0;
+        let in_check = attacks.is_square_attacked(position, king_square, opp_side);
 
-        self.nodes += 1;
         let mut best_move = Move::empty();
         let old_alpha = alpha;
 
-        let moves = generate_moves(attacks, position);
+        let killers = self
+            .killers
+            .get(self.ply as usize)
+            .copied()
+            .unwrap_or([Move::empty(); 2]);
+        // Search the stored best move first, or at the root the best move of the previous
+        // iteration if it has been replaced
+        let hash_move = match entry {
+            Some(entry) if entry.best_move != Move::empty() => entry.best_move,
+            _ if self.ply == 0 => self.pv_table[0][0],
+            _ => Move::empty(),
+        };
+        let mut picker = MovePicker::new(hash_move, killers);
+        let mut legal_moves = 0;
 
-        for mov in moves.into_iter() {
-            let mut copy = position.clone();
+        while let Some(mov) = picker.next(attacks, position) {
+            let undo = position.make_move_unchecked(mov);
             self.ply += 1;
-            // Check move legality
-            if !copy.make_move(mov, attacks) {
-                self.ply -= 1;
-                // Skip move
-                continue;
-            }
+            self.history.push(undo.hash);
             legal_moves += 1;
 
             // Score current move
-            let score = -self.negamax(&mut copy, attacks, -beta, -alpha, depth - 1);
+            let score = -self.negamax(position, attacks, tt, -beta, -alpha, depth - 1);
             self.ply -= 1;
+            self.history.pop();
+            position.unmake_move(mov, undo);
+            if self.stopped {
+                return 0;
+            }
 
             // Fail-hard cutoff
             if score >= beta {
+                if !mov.extract_capture() && mov.extract_promoted_piece() == Piece::None {
+                    self.store_killer(mov);
+                }
+                tt.store(position.hash, depth, Bound::Lower, beta, mov, self.ply);
                 // Move fails high
                 return beta;
             }
@@ -76,29 +319,69 @@ impl Search {
             if score > alpha {
                 // Principal variation move
                 alpha = score;
-                // If root move
-                if self.ply == 0 {
-                    best_move = mov;
-                }
+                self.update_pv(mov);
+                best_move = mov;
             }
         }
         // Check if any legal moves
         if legal_moves == 0 {
             if in_check {
                 // Return mating score (ply is added so that faster mates are prioritized)
-                return -49000 + self.ply;
+                return -MATE_VALUE + self.ply;
             } else {
                 // Return drawing score
                 return 0;
             }
         }
-        // Set new best move
         if old_alpha != alpha {
-            self.best_move = best_move;
+            tt.store(
+                position.hash,
+                depth,
+                Bound::Exact,
+                alpha,
+                best_move,
+                self.ply,
+            );
+            // Set new best move
+            if self.ply == 0 {
+                self.best_move = best_move;
+            }
+        } else {
+            // Move fails low
+            tt.store(
+                position.hash,
+                depth,
+                Bound::Upper,
+                alpha,
+                Move::empty(),
+                self.ply,
+            );
         }
-        // Move fails low
         alpha
     }
+
+    /// Makes `mov` followed by the line found from the next ply the principal variation of the
+    /// current ply
+    #[inline]
+    fn update_pv(&mut self, mov: Move) {
+        let ply = self.ply as usize;
+        self.pv_table[ply][ply] = mov;
+        let next_length = self.pv_length[ply + 1];
+        let (rows, next_rows) = self.pv_table.split_at_mut(ply + 1);
+        rows[ply][ply + 1..next_length].copy_from_slice(&next_rows[0][ply + 1..next_length]);
+        self.pv_length[ply] = next_length;
+    }
+
+    /// Remembers a quiet move that caused a cutoff, to be tried early at the same ply elsewhere
+    #[inline]
+    fn store_killer(&mut self, mov: Move) {
+        if let Some(killers) = self.killers.get_mut(self.ply as usize) {
+            if killers[0] != mov {
+                killers[1] = killers[0];
+                killers[0] = mov;
+            }
+        }
+    }
 }
 
 impl Default for Search {
@@ -106,3 +389,140 @@ impl Default for Search {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        attacks::ATTACKS,
+        move_gen::generate_legal_moves,
+        move_list::Move,
+        transposition::{Bound, TranspositionTable},
+        utils::fen::{parse_fen, START_POSITION, TRICKY_POSITION},
+    };
+
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    };
+
+    use super::{Search, SearchLimits, CHECK_INTERVAL, INFINITY, MATE_VALUE};
+
+    #[test]
+    fn test_mate_in_one() {
+        let mut pos = parse_fen("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1").unwrap();
+        let mut tt = TranspositionTable::new(1);
+        let mut search = Search::new();
+        let limits = SearchLimits {
+            depth: Some(3),
+            ..SearchLimits::default()
+        };
+        let best_move = search.iterative_deepening(&mut pos, &ATTACKS, &mut tt, &limits);
+        assert_eq!(best_move.to_string(), "a1a8");
+        assert!(search.pv()[0] == best_move);
+
+        let score = Search::new().negamax(&mut pos, &ATTACKS, &mut tt, -INFINITY, INFINITY, 3);
+        assert_eq!(score, MATE_VALUE - 1);
+        // The root is stored as an exact mate in one with its move
+        let entry = tt.probe(pos.hash).unwrap();
+        assert_eq!(
+            (entry.bound, entry.score(0)),
+            (Bound::Exact, MATE_VALUE - 1)
+        );
+        assert!(entry.best_move == best_move);
+    }
+
+    #[test]
+    fn test_mate_beats_fifty_move_rule() {
+        // The mating move is the hundredth halfmove without a capture or pawn move
+        let mut pos = parse_fen("6k1/5ppp/8/8/8/8/8/R5K1 w - - 99 80").unwrap();
+        let mut tt = TranspositionTable::new(1);
+        let score = Search::new().negamax(&mut pos, &ATTACKS, &mut tt, -INFINITY, INFINITY, 3);
+        assert_eq!(score, MATE_VALUE - 1);
+    }
+
+    #[test]
+    fn test_pv_is_legal() {
+        let mut pos = parse_fen(TRICKY_POSITION).unwrap();
+        let mut tt = TranspositionTable::new(1);
+        let mut search = Search::new();
+        let limits = SearchLimits {
+            depth: Some(4),
+            ..SearchLimits::default()
+        };
+        let best_move = search.iterative_deepening(&mut pos, &ATTACKS, &mut tt, &limits);
+        let pv = search.pv();
+        assert_eq!(pv.len(), 4);
+        assert!(pv[0] == best_move);
+
+        let mut child = pos.clone();
+        for &mov in pv {
+            assert!(
+                generate_legal_moves(&ATTACKS, &child).contains(mov),
+                "{}",
+                mov
+            );
+            child.make_move_unchecked(mov);
+        }
+    }
+
+    #[test]
+    fn test_node_limit() {
+        let mut pos = parse_fen(START_POSITION).unwrap();
+        let mut tt = TranspositionTable::new(1);
+        let mut search = Search::new();
+        let limits = SearchLimits {
+            nodes: Some(1000),
+            ..SearchLimits::default()
+        };
+        let best_move = search.iterative_deepening(&mut pos, &ATTACKS, &mut tt, &limits);
+        // The limit is only checked every CHECK_INTERVAL nodes
+        assert!(search.nodes() <= 1000 + CHECK_INTERVAL);
+        assert!(generate_legal_moves(&ATTACKS, &pos).contains(best_move));
+
+        // Nothing to play
+        let mut pos = parse_fen("7k/5Q2/6K1/8/8/8/8/8 b - - 0 1").unwrap();
+        let best_move = Search::new().iterative_deepening(&mut pos, &ATTACKS, &mut tt, &limits);
+        assert!(best_move == Move::empty());
+    }
+    #[test]
+    fn test_stop_flag() {
+        let mut pos = parse_fen(TRICKY_POSITION).unwrap();
+        let mut tt = TranspositionTable::new(1);
+        let stop = Arc::new(AtomicBool::new(false));
+        let mut search = Search::new();
+        search.set_stop_flag(stop.clone());
+
+        // A flag set before the search starts still lets the first iteration complete
+        stop.store(true, Ordering::Relaxed);
+        let best_move =
+            search.iterative_deepening(&mut pos, &ATTACKS, &mut tt, &SearchLimits::default());
+        assert!(generate_legal_moves(&ATTACKS, &pos).contains(best_move));
+        // Without the flag nothing would stop it before MAX_DEPTH
+        assert!(search.nodes() <= 2 * CHECK_INTERVAL);
+    }
+
+    #[test]
+    fn test_transposition_table_saves_nodes() {
+        let limits = SearchLimits {
+            depth: Some(5),
+            ..SearchLimits::default()
+        };
+        let mut pos = parse_fen(TRICKY_POSITION).unwrap();
+        let mut tt = TranspositionTable::new(1);
+        let mut search = Search::new();
+        let best_move = search.iterative_deepening(&mut pos, &ATTACKS, &mut tt, &limits);
+        let first_nodes = search.nodes();
+
+        // The same search again finds the results of the first one
+        let mut search = Search::new();
+        let repeat_move = search.iterative_deepening(&mut pos, &ATTACKS, &mut tt, &limits);
+        assert!(repeat_move == best_move);
+        assert!(search.nodes() < first_nodes / 2);
+
+        // A table of a single bucket is almost no help
+        let mut search = Search::new();
+        tt.resize(0).unwrap();
+        search.iterative_deepening(&mut pos, &ATTACKS, &mut tt, &limits);
+        assert!(search.nodes() > first_nodes);
+    }
+}
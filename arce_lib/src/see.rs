@@ -0,0 +1,176 @@
+use crate::{
+    attacks::{Attacks, ATTACKS},
+    bitboard::BitBoard,
+    move_list::Move,
+    piece::{Piece, BLACK_PIECES, WHITE_PIECES},
+    position::Position,
+    side::Side,
+    square::Square,
+};
+
+/// Piece values used by the exchange, pawn to king. The king is worth more than everything else
+/// together, so a king capture ends the exchange in favour of the side that made it.
+const SEE_VALUES: [i32; 6] = [100, 300, 350, 500, 1000, 20000];
+
+/// Longest possible capture sequence on one square: every piece on the board
+const MAX_EXCHANGE: usize = 32;
+
+#[inline]
+fn see_value(piece: Piece) -> i32 {
+    SEE_VALUES[piece as usize % 6]
+}
+
+impl Position {
+    /// Static Exchange Evaluation: the material the side to move wins or loses from `mov` once
+    /// both sides have taken turns recapturing on its target square with their least valuable
+    /// attacker, each stopping when carrying on would lose more. Sliders behind other attackers
+    /// join the exchange as the pieces in front of them leave the square's lines.
+    ///
+    /// Quiet moves start from nothing, so a negative score means the moved piece can be won.
+    /// Castling always scores 0.
+    pub fn see_with(&self, mov: Move, attacks: &Attacks) -> i32 {
+        if mov.extract_castling() {
+            return 0;
+        }
+        let source_square = mov.extract_source();
+        let target_square = mov.extract_target();
+        let mut occupancy = self.all_occupancies;
+        let mut gain = [0; MAX_EXCHANGE];
+
+        gain[0] = if mov.extract_en_passant() {
+            // The captured pawn is beside the target square, not on it
+            let captured =
+                Square::from_fr_unchecked(target_square as u8 & 0b111, source_square as u8 >> 3);
+            occupancy.pop_bit(captured);
+            see_value(Piece::WPawn)
+        } else {
+            self.piece_on(target_square).map_or(0, see_value)
+        };
+        // Value of the piece now standing on the target square
+        let mut on_square = see_value(mov.extract_piece());
+        let promoted_piece = mov.extract_promoted_piece();
+        if promoted_piece != Piece::None {
+            gain[0] += see_value(promoted_piece) - see_value(Piece::WPawn);
+            on_square = see_value(promoted_piece);
+        }
+        occupancy.pop_bit(source_square);
+
+        let diagonal = self.w_bishops | self.b_bishops | self.w_queens | self.b_queens;
+        let orthogonal = self.w_rooks | self.b_rooks | self.w_queens | self.b_queens;
+        let mut attackers = attacks.attackers_to(self, target_square, occupancy) & occupancy;
+        let mut side = self.side.opponent();
+        let mut depth = 0;
+        while depth + 1 < MAX_EXCHANGE {
+            let attacker = match self.least_valuable_attacker(attackers, side) {
+                Some(attacker) => attacker,
+                None => break,
+            };
+            depth += 1;
+            gain[depth] = on_square - gain[depth - 1];
+            // Neither side can do better by carrying on
+            if (-gain[depth - 1]).max(gain[depth]) < 0 {
+                break;
+            }
+            let (square, piece) = attacker;
+            on_square = see_value(piece);
+            occupancy.pop_bit(square);
+            // Sliders that were behind the piece that just captured
+            attackers |= (attacks.get_bishop_attacks(target_square, occupancy) & diagonal)
+                | (attacks.get_rook_attacks(target_square, occupancy) & orthogonal);
+            attackers &= occupancy;
+            side = side.opponent();
+        }
+
+        // Each side either makes its capture or stands pat, whichever is better for it
+        while depth > 0 {
+            gain[depth - 1] = -(-gain[depth - 1]).max(gain[depth]);
+            depth -= 1;
+        }
+        gain[0]
+    }
+
+    /// Returns true if the static exchange evaluation of `mov` is at least `threshold`, such as
+    /// `see_ge_with(mov, 0, attacks)` for a capture that does not lose material
+    #[inline]
+    pub fn see_ge_with(&self, mov: Move, threshold: i32, attacks: &Attacks) -> bool {
+        self.see_with(mov, attacks) >= threshold
+    }
+
+    /// `see_with` using the global `ATTACKS` tables
+    #[inline]
+    pub fn see(&self, mov: Move) -> i32 {
+        self.see_with(mov, &ATTACKS)
+    }
+
+    /// `see_ge_with` using the global `ATTACKS` tables
+    #[inline]
+    pub fn see_ge(&self, mov: Move, threshold: i32) -> bool {
+        self.see_ge_with(mov, threshold, &ATTACKS)
+    }
+
+    /// Picks the least valuable piece of `side` among `attackers`
+    #[inline]
+    fn least_valuable_attacker(&self, attackers: BitBoard, side: Side) -> Option<(Square, Piece)> {
+        let pieces = match side {
+            Side::White => WHITE_PIECES,
+            Side::Black => BLACK_PIECES,
+        };
+        pieces.iter().find_map(|&piece| {
+            (self.get_piece_bitboard(piece) & attackers)
+                .get_lsb_square()
+                .map(|square| (square, piece))
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        attacks::{Attacks, ATTACKS},
+        move_gen::generate_legal_moves,
+        move_list::Move,
+        position::Position,
+        utils::fen::{parse_fen, START_POSITION, TRICKY_POSITION},
+    };
+
+    fn find(pos: &Position, attacks: &Attacks, mov: &str) -> Move {
+        generate_legal_moves(attacks, pos)
+            .into_iter()
+            .find(|m| m.to_string() == mov)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_see() {
+        let cases = [
+            (START_POSITION, "g1f3", 0),
+            // Undefended and defended pawns
+            ("4k3/8/8/3p4/4P3/8/8/4K3 w - - 0 1", "e4d5", 100),
+            ("4k3/8/2p5/3p4/4P3/8/8/4K3 w - - 0 1", "e4d5", 0),
+            ("4k3/8/2p5/3p4/8/8/8/3RK3 w - - 0 1", "d1d5", -400),
+            // Quiet move onto an attacked square
+            ("4k3/8/8/8/2p5/8/3N4/4K3 w - - 0 1", "d2b3", -300),
+            // X-rays on both sides
+            ("3rk3/8/8/3p4/8/8/3R4/3RK3 w - - 0 1", "d2d5", 100),
+            ("3rk3/3r4/8/3p4/8/8/3R4/3RK3 w - - 0 1", "d2d5", -400),
+            ("4k3/8/2p5/3p4/8/5B2/6Q1/4K3 w - - 0 1", "f3d5", -150),
+            // The king cannot recapture onto a defended square
+            ("8/8/4k3/3p4/2P5/1B6/8/4K3 w - - 0 1", "c4d5", 100),
+            ("8/8/4k3/3p4/2P5/8/8/4K3 w - - 0 1", "c4d5", 0),
+            // En passant and promotions
+            ("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1", "e5d6", 100),
+            ("3rk3/4P3/8/8/8/8/8/4K3 w - - 0 1", "e7d8q", 400),
+            ("3rk3/4P3/8/8/8/8/8/4K3 w - - 0 1", "e7d8n", 400),
+            ("3r4/4P3/8/8/8/8/8/k3K3 w - - 0 1", "e7d8q", 1400),
+            (TRICKY_POSITION, "e2a6", 350),
+            (TRICKY_POSITION, "f3f6", -700),
+        ];
+        for (fen, mov, score) in cases.iter() {
+            let pos = parse_fen(fen).unwrap();
+            let mov = find(&pos, &ATTACKS, mov);
+            assert_eq!(pos.see_with(mov, &ATTACKS), *score, "{} in {}", mov, fen);
+            assert!(pos.see_ge_with(mov, *score, &ATTACKS));
+            assert!(!pos.see_ge_with(mov, *score + 1, &ATTACKS));
+        }
+    }
+}
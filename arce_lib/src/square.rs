@@ -1,4 +1,3 @@
-use core::panic;
 use std::{convert::TryFrom, fmt::Display};
 
 pub const SQUARES: [Square; 64] = [
@@ -0,0 +1,303 @@
+use std::{fmt::Display, mem};
+
+use crate::{move_list::Move, search::MATE_SCORE};
+
+/// Size of a table made with `TranspositionTable::default`, in megabytes
+pub const DEFAULT_HASH_MB: usize = 16;
+
+/// A table size that could not be allocated
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HashSizeError {
+    pub mb: usize,
+}
+
+impl Display for HashSizeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "not enough memory for a {} MB hash table", self.mb)
+    }
+}
+
+impl std::error::Error for HashSizeError {}
+
+/// How a stored score relates to the true score of the position
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Bound {
+    /// Every move was searched inside the window, so the score is exact
+    Exact,
+    /// A move failed high, so the true score is at least the stored one
+    Lower,
+    /// Every move failed low, so the true score is at most the stored one
+    Upper,
+}
+
+/// The result of searching one position
+#[derive(Clone, Copy)]
+pub struct TtEntry {
+    key: u64,
+    pub best_move: Move,
+    score: i32,
+    pub depth: u8,
+    pub bound: Bound,
+    /// Search the entry was stored in, so that old entries are replaced first
+    age: u8,
+}
+
+impl TtEntry {
+    const fn empty() -> TtEntry {
+        TtEntry {
+            key: 0,
+            best_move: Move::empty(),
+            score: 0,
+            depth: 0,
+            bound: Bound::Upper,
+            age: 0,
+        }
+    }
+
+    /// The stored score seen from `ply` moves after the root. Mate scores are stored relative to
+    /// the position itself, so that a transposition reached at a different ply gets the right
+    /// distance to mate.
+    #[inline]
+    pub fn score(&self, ply: i32) -> i32 {
+        if self.score > MATE_SCORE {
+            self.score - ply
+        } else if self.score < -MATE_SCORE {
+            self.score + ply
+        } else {
+            self.score
+        }
+    }
+}
+
+/// Table of search results keyed by Zobrist hash, kept between searches.
+///
+/// Each bucket has two entries. The first keeps the deepest result unless it is from an older
+/// search, and anything that does not replace it goes into the second, which is always
+/// overwritten.
+pub struct TranspositionTable {
+    buckets: Vec<[TtEntry; 2]>,
+    age: u8,
+}
+
+impl TranspositionTable {
+    /// Panics if the table cannot be allocated, which `resize` reports as an error instead
+    pub fn new(mb: usize) -> TranspositionTable {
+        TranspositionTable {
+            buckets: Self::alloc(mb).unwrap_or_else(|err| panic!("{}", err)),
+            age: 0,
+        }
+    }
+
+    /// Reallocates the table with a new size, dropping every entry. The old table is freed
+    /// first so that the two never take memory at once, and if the new size cannot be
+    /// allocated the table is given back its old size and an error is returned.
+    pub fn resize(&mut self, mb: usize) -> Result<(), HashSizeError> {
+        let old_len = self.buckets.len();
+        self.buckets = Vec::new();
+        self.age = 0;
+        match Self::alloc(mb) {
+            Ok(buckets) => {
+                self.buckets = buckets;
+                Ok(())
+            }
+            Err(err) => {
+                self.buckets = vec![[TtEntry::empty(); 2]; old_len];
+                Err(err)
+            }
+        }
+    }
+
+    /// Allocates the buckets of an `mb` megabyte table, or at least one bucket, failing
+    /// instead of aborting when there is not enough memory
+    fn alloc(mb: usize) -> Result<Vec<[TtEntry; 2]>, HashSizeError> {
+        let len = mb.saturating_mul(1 << 20) / mem::size_of::<[TtEntry; 2]>();
+        let mut buckets = Vec::new();
+        buckets
+            .try_reserve_exact(len.max(1))
+            .map_err(|_| HashSizeError { mb })?;
+        buckets.resize(len.max(1), [TtEntry::empty(); 2]);
+        Ok(buckets)
+    }
+
+    /// Drops every entry, such as for a new game
+    pub fn clear(&mut self) {
+        self.buckets.fill([TtEntry::empty(); 2]);
+        self.age = 0;
+    }
+
+    /// Marks the entries stored so far as old, to be called before each search
+    pub fn new_search(&mut self) {
+        self.age = self.age.wrapping_add(1);
+    }
+
+    #[inline]
+    fn index(&self, hash: u64) -> usize {
+        ((hash as u128 * self.buckets.len() as u128) >> 64) as usize
+    }
+
+    /// Returns the entry stored for the position with this hash, if there is one
+    #[inline]
+    pub fn probe(&self, hash: u64) -> Option<TtEntry> {
+        self.buckets[self.index(hash)]
+            .iter()
+            .find(|entry| entry.key == hash && entry.depth > 0)
+            .copied()
+    }
+
+    /// Stores the result of searching the position with this hash to `depth`, `ply` moves after
+    /// the root. An empty `best_move` keeps the move already stored for the position.
+    #[inline]
+    pub fn store(
+        &mut self,
+        hash: u64,
+        depth: u8,
+        bound: Bound,
+        score: i32,
+        best_move: Move,
+        ply: i32,
+    ) {
+        let age = self.age;
+        let index = self.index(hash);
+        let bucket = &mut self.buckets[index];
+        let slot = if bucket[0].key == hash || bucket[0].age != age || depth >= bucket[0].depth {
+            0
+        } else {
+            1
+        };
+        let entry = &mut bucket[slot];
+
+        let best_move = if best_move == Move::empty() && entry.key == hash {
+            entry.best_move
+        } else {
+            best_move
+        };
+        // Store mate scores as the distance to mate from this position
+        let score = if score > MATE_SCORE {
+            score + ply
+        } else if score < -MATE_SCORE {
+            score - ply
+        } else {
+            score
+        };
+        *entry = TtEntry {
+            key: hash,
+            best_move,
+            score,
+            depth,
+            bound,
+            age,
+        };
+    }
+
+    /// How full the table is in permille, estimated from the first thousand entries, counting
+    /// only those from the current search as UCI expects
+    pub fn hashfull(&self) -> usize {
+        self.buckets
+            .iter()
+            .take(500)
+            .flatten()
+            .filter(|entry| entry.depth > 0 && entry.age == self.age)
+            .count()
+            * 1000
+            / (self.buckets.len().min(500) * 2)
+    }
+}
+
+impl Default for TranspositionTable {
+    fn default() -> Self {
+        TranspositionTable::new(DEFAULT_HASH_MB)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        attacks::ATTACKS,
+        move_gen::generate_legal_moves,
+        move_list::Move,
+        search::MATE_VALUE,
+        utils::fen::{parse_fen, START_POSITION},
+    };
+
+    use super::{Bound, HashSizeError, TranspositionTable};
+
+    #[test]
+    fn test_store_and_probe() {
+        let pos = parse_fen(START_POSITION).unwrap();
+        let moves = generate_legal_moves(&ATTACKS, &pos);
+        let mut tt = TranspositionTable::new(1);
+        tt.new_search();
+        assert!(tt.probe(pos.hash).is_none());
+
+        tt.store(pos.hash, 5, Bound::Exact, 30, moves[0], 0);
+        let entry = tt.probe(pos.hash).unwrap();
+        assert_eq!(
+            (entry.depth, entry.bound, entry.score(3)),
+            (5, Bound::Exact, 30)
+        );
+        assert!(entry.best_move == moves[0]);
+
+        // A result without a move keeps the old one
+        tt.store(pos.hash, 6, Bound::Upper, -10, Move::empty(), 0);
+        let entry = tt.probe(pos.hash).unwrap();
+        assert_eq!(
+            (entry.depth, entry.bound, entry.score(0)),
+            (6, Bound::Upper, -10)
+        );
+        assert!(entry.best_move == moves[0]);
+
+        // Mate 3 plies after a position stored 2 plies from the root, seen from other plies
+        tt.store(pos.hash, 6, Bound::Exact, MATE_VALUE - 5, moves[1], 2);
+        assert_eq!(tt.probe(pos.hash).unwrap().score(0), MATE_VALUE - 3);
+        assert_eq!(tt.probe(pos.hash).unwrap().score(6), MATE_VALUE - 9);
+        tt.store(pos.hash, 6, Bound::Exact, -MATE_VALUE + 5, moves[1], 2);
+        assert_eq!(tt.probe(pos.hash).unwrap().score(6), -MATE_VALUE + 9);
+
+        tt.clear();
+        assert!(tt.probe(pos.hash).is_none());
+    }
+
+    #[test]
+    fn test_replacement() {
+        // A single bucket, so that every hash collides
+        let mut tt = TranspositionTable::new(0);
+        tt.new_search();
+        tt.store(1, 8, Bound::Exact, 1, Move::empty(), 0);
+        // Shallower results go into the always replace entry
+        tt.store(2, 3, Bound::Exact, 2, Move::empty(), 0);
+        tt.store(3, 4, Bound::Exact, 3, Move::empty(), 0);
+        assert_eq!(tt.probe(1).unwrap().depth, 8);
+        assert!(tt.probe(2).is_none());
+        assert_eq!(tt.probe(3).unwrap().depth, 4);
+        // Deeper results take the depth preferred entry
+        tt.store(4, 9, Bound::Exact, 4, Move::empty(), 0);
+        assert!(tt.probe(1).is_none());
+        assert_eq!(tt.probe(4).unwrap().depth, 9);
+        assert_eq!(tt.hashfull(), 1000);
+
+        // Entries from an earlier search are replaced whatever their depth
+        tt.new_search();
+        assert_eq!(tt.hashfull(), 0);
+        tt.store(5, 1, Bound::Exact, 5, Move::empty(), 0);
+        assert!(tt.probe(4).is_none());
+        assert_eq!(tt.probe(5).unwrap().depth, 1);
+        assert_eq!(tt.hashfull(), 500);
+    }
+
+    #[test]
+    fn test_resize() {
+        let mut tt = TranspositionTable::new(1);
+        let len = tt.buckets.len();
+        tt.new_search();
+        tt.store(1, 4, Bound::Exact, 0, Move::empty(), 0);
+        tt.resize(2).unwrap();
+        assert_eq!(tt.buckets.len(), 2 * len);
+        assert!(tt.probe(1).is_none());
+
+        // A size that cannot be allocated leaves the table at its old size
+        assert_eq!(tt.resize(usize::MAX), Err(HashSizeError { mb: usize::MAX }));
+        assert_eq!(tt.buckets.len(), 2 * len);
+        tt.store(1, 4, Bound::Exact, 0, Move::empty(), 0);
+        assert!(tt.probe(1).is_some());
+    }
+}
@@ -0,0 +1,129 @@
+use std::fmt::Display;
+
+use crate::{
+    position::Position,
+    utils::fen::{parse_fen, FenError},
+};
+
+/// A position from a perft suite with the expected leaf node count at each listed depth
+#[derive(Clone, Debug)]
+pub struct PerftEntry {
+    pub fen: String,
+    pub pos: Position,
+    /// (Depth, nodes), in the order they are listed
+    pub depths: Vec<(u32, u64)>,
+}
+
+/// Reasons a perft suite line can fail to parse. Offsets are byte offsets into the parsed line.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EpdError {
+    /// The position before the first ';' is not a valid FEN
+    Fen(FenError),
+    /// An operation is not of the form `D<depth> <nodes>`
+    InvalidOperation { offset: usize },
+    /// The line has no depth operations
+    NoDepths,
+}
+
+impl Display for EpdError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EpdError::Fen(err) => write!(f, "invalid FEN: {}", err),
+            EpdError::InvalidOperation { offset } => {
+                write!(f, "expected D<depth> <nodes> (at byte {})", offset)
+            }
+            EpdError::NoDepths => write!(f, "no depths to check"),
+        }
+    }
+}
+
+impl std::error::Error for EpdError {}
+
+impl From<FenError> for EpdError {
+    fn from(err: FenError) -> EpdError {
+        EpdError::Fen(err)
+    }
+}
+
+/// Parses a line of a perft suite file, a FEN followed by the expected node counts such as
+/// `rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1 ;D1 20 ;D2 400 ;D3 8902`. The move
+/// counters of the FEN may be left out.
+pub fn parse_perft_epd(line: &str) -> Result<PerftEntry, EpdError> {
+    let mut fields = line.split(';');
+    let fen = fields.next().unwrap_or("").trim();
+    let pos = parse_fen(fen)?;
+
+    let mut depths = Vec::new();
+    for field in fields {
+        let field = field.trim_start();
+        let offset = field.as_ptr() as usize - line.as_ptr() as usize;
+        let mut parts = field.split_ascii_whitespace();
+        let depth = parts
+            .next()
+            .and_then(|depth| depth.strip_prefix('D'))
+            .and_then(|depth| depth.parse().ok());
+        let nodes = parts.next().and_then(|nodes| nodes.parse().ok());
+        match (depth, nodes, parts.next()) {
+            (Some(depth), Some(nodes), None) => depths.push((depth, nodes)),
+            _ => return Err(EpdError::InvalidOperation { offset }),
+        }
+    }
+    if depths.is_empty() {
+        return Err(EpdError::NoDepths);
+    }
+
+    Ok(PerftEntry {
+        fen: fen.to_string(),
+        pos,
+        depths,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::utils::fen::{FenError, START_POSITION};
+
+    use super::{parse_perft_epd, EpdError};
+
+    #[test]
+    fn test_parse_perft_epd() {
+        let entry = parse_perft_epd(
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1 ;D1 20 ;D2 400 ;D3 8902",
+        )
+        .unwrap();
+        assert_eq!(entry.fen, START_POSITION);
+        assert_eq!(entry.pos.to_fen(), START_POSITION);
+        assert_eq!(entry.depths, vec![(1, 20), (2, 400), (3, 8902)]);
+
+        // Without move counters, spacing or a trailing newline
+        let entry = parse_perft_epd("4k3/8/8/8/8/8/8/4K2R w K -;D1 15;D2 66\n").unwrap();
+        assert_eq!(entry.fen, "4k3/8/8/8/8/8/8/4K2R w K -");
+        assert_eq!(entry.depths, vec![(1, 15), (2, 66)]);
+
+        let cases = [
+            (
+                "4k3/8/8/8/8/8/8/4K2X w K - ;D1 15",
+                EpdError::Fen(FenError::InvalidPiece {
+                    ch: 'X',
+                    offset: 19,
+                }),
+            ),
+            (
+                "4k3/8/8/8/8/8/8/4K2R w K - ;D1 15 ;D2",
+                EpdError::InvalidOperation { offset: 35 },
+            ),
+            (
+                "4k3/8/8/8/8/8/8/4K2R w K - ;P1 15",
+                EpdError::InvalidOperation { offset: 28 },
+            ),
+            (
+                "4k3/8/8/8/8/8/8/4K2R w K - ;D1 15 16",
+                EpdError::InvalidOperation { offset: 28 },
+            ),
+            ("4k3/8/8/8/8/8/8/4K2R w K -", EpdError::NoDepths),
+        ];
+        for (line, err) in cases.iter() {
+            assert_eq!(parse_perft_epd(line).unwrap_err(), *err, "{}", line);
+        }
+    }
+}
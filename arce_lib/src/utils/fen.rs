@@ -1,7 +1,8 @@
-use std::convert::TryFrom;
+use std::{convert::TryFrom, fmt::Display};
 
 use crate::{
-    bitboard::BitBoard, castle_rights::CastleRights, position::Position, side::Side, square::Square,
+    bitboard::BitBoard, castle_rights::CastleRights, piece::Piece, position::Position, side::Side,
+    square::Square,
 };
 
 pub const EMPTY_BOARD: &str = "8/8/8/8/8/8/8/8 w - -";
@@ -13,12 +14,70 @@ pub const KILLER_POSITION: &str =
 pub const CMK_POSITION: &str =
     "r2q1rk1/ppp2ppp/2n1bn2/2b1p3/3pP3/3P1NPP/PPP1NPB1/R1BQ1RK1 b - - 0 9";
 
-pub fn parse_fen(fen: &str) -> Option<Position> {
-    let mut fen = fen.trim().split_ascii_whitespace();
-    let mut fen_board = fen.next()?.split('/');
-    let fen_side = fen.next()?;
-    let fen_castle = fen.next()?;
-    let fen_en_passant = fen.next()?;
+/// Reasons a FEN string can fail to parse. Offsets are byte offsets into the parsed string.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FenError {
+    /// The board does not have eight ranks
+    RankCount { offset: usize },
+    /// A rank (0 is the eighth rank) does not have eight files
+    FileCount { rank: u8, offset: usize },
+    /// A board character is not a piece or a valid empty square count
+    InvalidPiece { ch: char, offset: usize },
+    /// The side to move is missing or not 'w' or 'b'
+    InvalidSide { offset: usize },
+    /// The castling field is missing, repeats a right or contains an unknown character
+    InvalidCastling { offset: usize },
+    /// The en passant field is missing or not '-' or a square on the third or sixth rank
+    InvalidEnPassant { offset: usize },
+    /// The halfmove clock or fullmove number is not a number
+    InvalidMoveCounter { offset: usize },
+}
+
+impl Display for FenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FenError::RankCount { offset } => {
+                write!(f, "board does not have 8 ranks (at byte {})", offset)
+            }
+            FenError::FileCount { rank, offset } => write!(
+                f,
+                "rank {} does not have 8 files (at byte {})",
+                8 - rank,
+                offset
+            ),
+            FenError::InvalidPiece { ch, offset } => {
+                write!(f, "invalid piece '{}' (at byte {})", ch, offset)
+            }
+            FenError::InvalidSide { offset } => {
+                write!(f, "invalid side to move (at byte {})", offset)
+            }
+            FenError::InvalidCastling { offset } => {
+                write!(f, "invalid castling rights (at byte {})", offset)
+            }
+            FenError::InvalidEnPassant { offset } => {
+                write!(f, "invalid en passant square (at byte {})", offset)
+            }
+            FenError::InvalidMoveCounter { offset } => {
+                write!(f, "invalid move counter (at byte {})", offset)
+            }
+        }
+    }
+}
+
+impl std::error::Error for FenError {}
+
+pub fn parse_fen(fen: &str) -> Result<Position, FenError> {
+    // Split into fields, keeping the byte offset of each one
+    let mut fields = fen
+        .split_ascii_whitespace()
+        .map(|field| (field.as_ptr() as usize - fen.as_ptr() as usize, field));
+    let (board_offset, fen_board) = fields.next().unwrap_or((fen.len(), ""));
+    let fen_side = fields.next();
+    let fen_castle = fields.next();
+    let fen_en_passant = fields.next();
+    // Move counters are optional and default to the start of a game
+    let fen_halfmove_clock = fields.next();
+    let fen_fullmove_number = fields.next();
 
     let mut w_pawns = BitBoard::empty();
     let mut w_knights = BitBoard::empty();
@@ -32,39 +91,70 @@ pub fn parse_fen(fen: &str) -> Option<Position> {
     let mut b_rooks = BitBoard::empty();
     let mut b_queens = BitBoard::empty();
     let mut b_king = BitBoard::empty();
+    let mut mailbox = [None; 64];
 
-    for rank in (0..8).rev() {
-        let mut file = 0;
-        for ch in fen_board.next()?.chars() {
-            let square = Square::from_fr_unchecked(file, rank);
-            match ch {
-                '1' => (),
-                '2' => file += 1,
-                '3' => file += 2,
-                '4' => file += 3,
-                '5' => file += 4,
-                '6' => file += 5,
-                '7' => file += 6,
-                '8' => file += 7,
-                'P' => w_pawns.set_bit(square),
-                'N' => w_knights.set_bit(square),
-                'B' => w_bishops.set_bit(square),
-                'R' => w_rooks.set_bit(square),
-                'Q' => w_queens.set_bit(square),
-                'K' => w_king.set_bit(square),
-                'p' => b_pawns.set_bit(square),
-                'n' => b_knights.set_bit(square),
-                'b' => b_bishops.set_bit(square),
-                'r' => b_rooks.set_bit(square),
-                'q' => b_queens.set_bit(square),
-                'k' => b_king.set_bit(square),
-                _ => return None,
+    let mut rank = 8;
+    let mut file = 0;
+    for (i, ch) in fen_board.char_indices() {
+        let offset = board_offset + i;
+        if ch == '/' {
+            if file != 8 {
+                return Err(FenError::FileCount {
+                    rank: 8 - rank,
+                    offset,
+                });
+            }
+            if rank == 1 {
+                return Err(FenError::RankCount { offset });
             }
-            file += 1;
+            rank -= 1;
+            file = 0;
+            continue;
         }
-        if file != 8 {
-            return None;
+        if file >= 8 {
+            return Err(FenError::FileCount {
+                rank: 8 - rank,
+                offset,
+            });
         }
+        if let Some(empty) = ch.to_digit(10).filter(|d| (1..=8).contains(d)) {
+            file += empty as u8;
+            if file > 8 {
+                return Err(FenError::FileCount {
+                    rank: 8 - rank,
+                    offset,
+                });
+            }
+            continue;
+        }
+        let square = Square::from_fr_unchecked(file, rank - 1);
+        match ch {
+            'P' => w_pawns.set_bit(square),
+            'N' => w_knights.set_bit(square),
+            'B' => w_bishops.set_bit(square),
+            'R' => w_rooks.set_bit(square),
+            'Q' => w_queens.set_bit(square),
+            'K' => w_king.set_bit(square),
+            'p' => b_pawns.set_bit(square),
+            'n' => b_knights.set_bit(square),
+            'b' => b_bishops.set_bit(square),
+            'r' => b_rooks.set_bit(square),
+            'q' => b_queens.set_bit(square),
+            'k' => b_king.set_bit(square),
+            _ => return Err(FenError::InvalidPiece { ch, offset }),
+        }
+        mailbox[square as usize] = Some(Piece::from_ascii(ch));
+        file += 1;
+    }
+    let board_end = board_offset + fen_board.len();
+    if rank != 1 {
+        return Err(FenError::RankCount { offset: board_end });
+    }
+    if file != 8 {
+        return Err(FenError::FileCount {
+            rank: 7,
+            offset: board_end,
+        });
     }
 
     let w_occupancies = w_pawns | w_knights | w_bishops | w_rooks | w_queens | w_king;
@@ -72,19 +162,32 @@ pub fn parse_fen(fen: &str) -> Option<Position> {
     let all_occupancies = w_occupancies | b_occupancies;
 
     let side = match fen_side {
-        "w" => Side::White,
-        "b" => Side::Black,
-        _ => return None,
+        Some((_, "w")) => Side::White,
+        Some((_, "b")) => Side::Black,
+        Some((offset, _)) => return Err(FenError::InvalidSide { offset }),
+        None => return Err(FenError::InvalidSide { offset: fen.len() }),
     };
 
-    let en_passant = match Square::try_from(fen_en_passant) {
-        Ok(square) => Some(square),
-        Err(_) => None,
+    let castle = match fen_castle {
+        Some((offset, fen_castle)) => {
+            parse_castling(fen_castle, offset, [w_king, b_king], [w_rooks, b_rooks])?
+        }
+        None => return Err(FenError::InvalidCastling { offset: fen.len() }),
     };
 
-    let castle = CastleRights::from(fen_castle);
+    let en_passant = match fen_en_passant {
+        Some((_, "-")) => None,
+        Some((offset, fen_en_passant)) => match Square::try_from(fen_en_passant) {
+            Ok(square) if matches!(square as u8 / 8, 2 | 5) => Some(square),
+            _ => return Err(FenError::InvalidEnPassant { offset }),
+        },
+        None => return Err(FenError::InvalidEnPassant { offset: fen.len() }),
+    };
 
-    Some(Position {
+    let halfmove_clock = parse_move_counter(fen_halfmove_clock, 0)?;
+    let fullmove_number = parse_move_counter(fen_fullmove_number, 1)?;
+
+    let mut pos = Position {
         w_pawns,
         w_knights,
         w_bishops,
@@ -100,8 +203,300 @@ pub fn parse_fen(fen: &str) -> Option<Position> {
         w_occupancies,
         b_occupancies,
         all_occupancies,
+        mailbox,
         side,
         en_passant,
         castle,
-    })
+        halfmove_clock,
+        fullmove_number,
+        hash: 0,
+    };
+    pos.hash = pos.gen_hash();
+
+    Ok(pos)
+}
+
+/// Parses a castling field in standard, X-FEN or Shredder-FEN form. `KQkq` castle with the
+/// outermost rook on that wing, while a file letter names the rook to castle with, which is how
+/// Chess960 positions with two rooks on one side of the king are written.
+fn parse_castling(
+    field: &str,
+    offset: usize,
+    kings: [BitBoard; 2],
+    rooks: [BitBoard; 2],
+) -> Result<CastleRights, FenError> {
+    let mut castle = CastleRights::none();
+    if field == "-" {
+        return Ok(castle);
+    }
+    for (i, ch) in field.char_indices() {
+        let error = FenError::InvalidCastling { offset: offset + i };
+        let (side, rank) = if ch.is_ascii_uppercase() {
+            (Side::White, 0)
+        } else {
+            (Side::Black, 7)
+        };
+        let king_file = (0..8)
+            .find(|&file| {
+                kings[side as usize]
+                    .get_bit(Square::from_fr_unchecked(file, rank))
+                    .is_not_empty()
+            })
+            .unwrap_or(4);
+        let is_rook = |file: &u8| {
+            rooks[side as usize]
+                .get_bit(Square::from_fr_unchecked(*file, rank))
+                .is_not_empty()
+        };
+        // Rights without a matching rook fall back to the corner so validation can report them
+        let (kingside, rook_file) = match ch.to_ascii_lowercase() {
+            'k' => (true, (king_file + 1..8).rev().find(is_rook).unwrap_or(7)),
+            'q' => (false, (0..king_file).find(is_rook).unwrap_or(0)),
+            file @ 'a'..='h' if file as u8 - b'a' != king_file => {
+                let file = file as u8 - b'a';
+                (file > king_file, file)
+            }
+            _ => return Err(error),
+        };
+        if castle.has(side, kingside) {
+            return Err(error);
+        }
+        castle.add(
+            side,
+            kingside,
+            Square::from_fr_unchecked(king_file, rank),
+            Square::from_fr_unchecked(rook_file, rank),
+        );
+    }
+    Ok(castle)
+}
+
+fn parse_move_counter(field: Option<(usize, &str)>, default: u16) -> Result<u16, FenError> {
+    match field {
+        Some((offset, counter)) => counter
+            .parse()
+            .map_err(|_| FenError::InvalidMoveCounter { offset }),
+        None => Ok(default),
+    }
+}
+
+/// Writes a position as a six field FEN string. Created with `Position::fen`.
+pub struct Fen<'a>(pub &'a Position);
+
+impl Display for Fen<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let pos = self.0;
+        for rank in (0..8).rev() {
+            let mut empty = 0;
+            for file in 0..8 {
+                let square = Square::from_fr_unchecked(file, rank);
+                match pos.piece_on(square) {
+                    Some(piece) => {
+                        if empty > 0 {
+                            write!(f, "{}", empty)?;
+                            empty = 0;
+                        }
+                        write!(f, "{}", piece.to_ascii())?;
+                    }
+                    None => empty += 1,
+                }
+            }
+            if empty > 0 {
+                write!(f, "{}", empty)?;
+            }
+            if rank > 0 {
+                write!(f, "/")?;
+            }
+        }
+
+        let side = match pos.side {
+            Side::White => 'w',
+            Side::Black => 'b',
+        };
+        write!(f, " {} {} ", side, pos.castle)?;
+        match pos.en_passant {
+            Some(square) => write!(f, "{}", square)?,
+            None => write!(f, "-")?,
+        }
+        write!(f, " {} {}", pos.halfmove_clock, pos.fullmove_number)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        attacks::{Attacks, ATTACKS},
+        move_gen::generate_moves,
+        position::Position,
+        side::Side,
+        square::Square,
+    };
+
+    use super::{
+        parse_fen, FenError, CMK_POSITION, EMPTY_BOARD, KILLER_POSITION, START_POSITION,
+        TRICKY_POSITION,
+    };
+
+    fn check_round_trip(pos: &Position) {
+        let fen = pos.to_fen();
+        let parsed = parse_fen(&fen).unwrap();
+        assert_eq!(parsed.to_fen(), fen);
+        assert_eq!(parsed.hash, pos.hash, "hash mismatch in {}", fen);
+    }
+
+    fn check_round_trip_tree(pos: &Position, attacks: &Attacks, depth: u32) {
+        check_round_trip(pos);
+        if depth == 0 {
+            return;
+        }
+        for mov in generate_moves(attacks, pos) {
+            let mut copy = pos.clone();
+            if copy.make_move(mov, attacks) {
+                check_round_trip_tree(&copy, attacks, depth - 1);
+            }
+        }
+    }
+
+    #[test]
+    fn test_fen_constants_round_trip() {
+        for fen in [
+            START_POSITION,
+            TRICKY_POSITION,
+            KILLER_POSITION,
+            CMK_POSITION,
+        ] {
+            assert_eq!(parse_fen(fen).unwrap().to_fen(), fen);
+        }
+        // Missing move counters are written with their defaults
+        assert_eq!(
+            parse_fen(EMPTY_BOARD).unwrap().to_fen(),
+            "8/8/8/8/8/8/8/8 w - - 0 1"
+        );
+    }
+
+    #[test]
+    fn test_castle_rights_round_trip() {
+        for rights in ["K", "Q", "k", "q", "Kq", "Qk", "KQk", "-"] {
+            let fen = format!("r3k2r/8/8/8/8/8/8/R3K2R w {} - 0 1", rights);
+            assert_eq!(parse_fen(&fen).unwrap().to_fen(), fen);
+        }
+        let pos = parse_fen("r3k2r/8/8/8/8/8/8/R3K2R w Kq - 0 1").unwrap();
+        assert!(pos.castle.wk() && !pos.castle.wq() && !pos.castle.bk() && pos.castle.bq());
+    }
+
+    #[test]
+    fn test_chess960_castling() {
+        // X-FEN letters castle with the outermost rook, Shredder-FEN letters name its file
+        let x_fen =
+            parse_fen("bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w KQkq - 2 9").unwrap();
+        let shredder =
+            parse_fen("bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w HFhf - 2 9").unwrap();
+        assert_eq!(x_fen.castle, shredder.castle);
+        assert_eq!(shredder.castle.king_square(Side::White), Square::G1);
+        assert_eq!(shredder.castle.rook_square(Side::White, false), Square::F1);
+        assert_eq!(shredder.castle.rook_square(Side::Black, true), Square::H8);
+        assert_eq!(
+            shredder.to_fen(),
+            "bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w HFhf - 2 9"
+        );
+        // Rights are written with file letters whenever any rook is away from its corner
+        for rights in ["HFhf", "Ff", "Fh"] {
+            let fen = format!(
+                "bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w {} - 2 9",
+                rights
+            );
+            let pos = parse_fen(&fen).unwrap();
+            assert_eq!(pos.to_fen(), fen);
+            assert_eq!(parse_fen(&pos.to_fen()).unwrap().castle, pos.castle);
+        }
+        let pos =
+            parse_fen("bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w Hh - 2 9").unwrap();
+        assert_eq!(pos.castle.to_string(), "Kk");
+
+        // With two rooks on one wing a file letter picks the inner one
+        let pos = parse_fen("4k3/8/8/8/8/8/8/1RR1K3 w Q - 0 1").unwrap();
+        assert_eq!(pos.castle.rook_square(Side::White, false), Square::B1);
+        let pos = parse_fen("4k3/8/8/8/8/8/8/1RR1K3 w C - 0 1").unwrap();
+        assert_eq!(pos.castle.rook_square(Side::White, false), Square::C1);
+        assert_eq!(pos.to_fen(), "4k3/8/8/8/8/8/8/1RR1K3 w C - 0 1");
+
+        check_round_trip_tree(
+            &parse_fen("b1q1rrkb/pppppppp/3nn3/8/P7/1PPP4/4PPPP/BQNNRKRB w GE - 1 9").unwrap(),
+            &ATTACKS,
+            3,
+        );
+    }
+
+    #[test]
+    fn test_perft_positions_round_trip() {
+        for fen in [
+            START_POSITION,
+            TRICKY_POSITION,
+            KILLER_POSITION,
+            CMK_POSITION,
+        ] {
+            check_round_trip_tree(&parse_fen(fen).unwrap(), &ATTACKS, 3);
+        }
+    }
+
+    #[test]
+    fn test_fen_errors() {
+        let cases = [
+            (
+                "8/8/8/8/8/8/8 w - - 0 1",
+                FenError::RankCount { offset: 13 },
+            ),
+            (
+                "8/8/8/8/8/8/8/8/8 w - - 0 1",
+                FenError::RankCount { offset: 15 },
+            ),
+            (
+                "8/8/8/7/8/8/8/8 w - - 0 1",
+                FenError::FileCount { rank: 3, offset: 7 },
+            ),
+            (
+                "8/8/8/44p/8/8/8/8 w - - 0 1",
+                FenError::FileCount { rank: 3, offset: 8 },
+            ),
+            (
+                "8/8/8/8/8/8/8/R6 w - - 0 1",
+                FenError::FileCount {
+                    rank: 7,
+                    offset: 16,
+                },
+            ),
+            (
+                "8/8/8/8/3x4/8/8/8 w - - 0 1",
+                FenError::InvalidPiece { ch: 'x', offset: 9 },
+            ),
+            (
+                "8/8/8/8/8/8/8/8 x - - 0 1",
+                FenError::InvalidSide { offset: 16 },
+            ),
+            ("8/8/8/8/8/8/8/8", FenError::InvalidSide { offset: 15 }),
+            (
+                "8/8/8/8/8/8/8/8 w KQkx - 0 1",
+                FenError::InvalidCastling { offset: 21 },
+            ),
+            (
+                "8/8/8/8/8/8/8/8 w KK - 0 1",
+                FenError::InvalidCastling { offset: 19 },
+            ),
+            (
+                "8/8/8/8/8/8/8/8 b - e4 0 1",
+                FenError::InvalidEnPassant { offset: 20 },
+            ),
+            (
+                "8/8/8/8/8/8/8/8 w - - -1 1",
+                FenError::InvalidMoveCounter { offset: 22 },
+            ),
+            (
+                "8/8/8/8/8/8/8/8 w - - 0 x",
+                FenError::InvalidMoveCounter { offset: 24 },
+            ),
+        ];
+        for (fen, err) in cases {
+            assert_eq!(parse_fen(fen).unwrap_err(), err, "{}", fen);
+        }
+    }
 }
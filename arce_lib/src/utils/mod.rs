@@ -1,3 +1,5 @@
+pub mod epd;
 pub mod fen;
 pub mod random;
+pub mod san;
 pub mod uci;
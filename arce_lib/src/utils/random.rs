@@ -1,6 +1,6 @@
 use std::cell::RefCell;
 
-thread_local! {pub static RAND: RefCell<Random> = RefCell::new(Random::new());}
+thread_local! {pub static RAND: RefCell<Random> = const { RefCell::new(Random::new()) };}
 
 pub struct Random {
     state: u32,
@@ -14,7 +14,7 @@ impl Random {
     }
 
     /// generate pseudorandom u32 with XOR shift algorithm.
-    pub fn rand_u32(&mut self) -> u32 {
+    pub const fn rand_u32(&mut self) -> u32 {
         let mut number = self.state;
         number ^= number << 13;
         number ^= number >> 17;
@@ -24,7 +24,7 @@ impl Random {
     }
 
     /// generate pseudorandom u64
-    pub fn rand_u64(&mut self) -> u64 {
+    pub const fn rand_u64(&mut self) -> u64 {
         let n1 = (self.rand_u32() & 0xFFFF) as u64;
         let n2 = (self.rand_u32() & 0xFFFF) as u64;
         let n3 = (self.rand_u32() & 0xFFFF) as u64;
@@ -37,3 +37,9 @@ impl Random {
         self.rand_u64() & self.rand_u64() & self.rand_u64()
     }
 }
+
+impl Default for Random {
+    fn default() -> Self {
+        Self::new()
+    }
+}
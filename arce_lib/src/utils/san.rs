@@ -0,0 +1,309 @@
+use std::{convert::TryFrom, fmt::Display};
+
+use crate::{
+    attacks::{Attacks, ATTACKS},
+    move_gen::generate_legal_moves,
+    move_list::Move,
+    piece::Piece,
+    position::Position,
+    square::Square,
+};
+
+/// Piece letters by piece type, pawn to king
+const PIECE_LETTERS: [char; 6] = ['P', 'N', 'B', 'R', 'Q', 'K'];
+
+/// Reasons a SAN move can fail to parse
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SanError {
+    /// The string is not written as a SAN move
+    Syntax(String),
+    /// No legal move matches
+    IllegalMove(String),
+    /// More than one legal move matches, so the move needs disambiguating
+    Ambiguous(String),
+}
+
+impl Display for SanError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SanError::Syntax(san) => write!(f, "'{}' is not a SAN move", san),
+            SanError::IllegalMove(san) => write!(f, "no legal move matches '{}'", san),
+            SanError::Ambiguous(san) => write!(f, "'{}' matches more than one move", san),
+        }
+    }
+}
+
+impl std::error::Error for SanError {}
+
+#[inline]
+fn piece_type(piece: Piece) -> usize {
+    piece as usize % 6
+}
+
+impl Position {
+    /// Writes a legal move in Standard Algebraic Notation, such as `Nbd7`, `exd5`, `e8=Q+`,
+    /// `O-O` or `Qh4#`
+    pub fn move_to_san_with(&self, mov: Move, attacks: &Attacks) -> String {
+        let source_square = mov.extract_source();
+        let target_square = mov.extract_target();
+        let piece = mov.extract_piece();
+        let mut san = String::with_capacity(8);
+
+        if mov.extract_castling() {
+            if target_square as u8 & 0b111 == 6 {
+                san.push_str("O-O");
+            } else {
+                san.push_str("O-O-O");
+            }
+        } else if piece_type(piece) == 0 {
+            if mov.extract_capture() {
+                san.push(file_char(source_square));
+                san.push('x');
+            }
+            san.push_str(&target_square.to_string());
+            let promoted = mov.extract_promoted_piece();
+            if promoted != Piece::None {
+                san.push('=');
+                san.push(PIECE_LETTERS[piece_type(promoted)]);
+            }
+        } else {
+            san.push(PIECE_LETTERS[piece_type(piece)]);
+            // Other pieces of the same kind that can reach the target
+            let others: Vec<Square> = generate_legal_moves(attacks, self)
+                .iter()
+                .filter(|m| {
+                    m.extract_piece() == piece
+                        && m.extract_target() == target_square
+                        && m.extract_source() != source_square
+                        && !m.extract_castling()
+                })
+                .map(|m| m.extract_source())
+                .collect();
+            if !others.is_empty() {
+                let (file, rank) = <(u8, u8)>::from(source_square);
+                if others.iter().all(|&s| <(u8, u8)>::from(s).0 != file) {
+                    san.push(file_char(source_square));
+                } else if others.iter().all(|&s| <(u8, u8)>::from(s).1 != rank) {
+                    san.push((b'1' + rank) as char);
+                } else {
+                    san.push_str(&source_square.to_string());
+                }
+            }
+            if mov.extract_capture() {
+                san.push('x');
+            }
+            san.push_str(&target_square.to_string());
+        }
+
+        let mut next = self.clone();
+        next.make_move_unchecked(mov);
+        if next.in_check(attacks) {
+            if generate_legal_moves(attacks, &next).is_empty() {
+                san.push('#');
+            } else {
+                san.push('+');
+            }
+        }
+        san
+    }
+
+    /// `move_to_san_with` using the global `ATTACKS` tables
+    #[inline]
+    pub fn move_to_san(&self, mov: Move) -> String {
+        self.move_to_san_with(mov, &ATTACKS)
+    }
+
+    /// Finds the legal move written in Standard Algebraic Notation. Check and annotation
+    /// suffixes are ignored, castling may be written with zeros and the capture sign may be left
+    /// out.
+    pub fn parse_san_with(&self, san: &str, attacks: &Attacks) -> Result<Move, SanError> {
+        let syntax_error = || SanError::Syntax(san.to_string());
+        let text = san.trim_end_matches(['+', '#', '!', '?']);
+        let moves = generate_legal_moves(attacks, self);
+
+        let castling_side = match text {
+            "O-O" | "0-0" => Some(true),
+            "O-O-O" | "0-0-0" => Some(false),
+            _ => None,
+        };
+        let matches: Vec<Move> = if let Some(kingside) = castling_side {
+            moves
+                .into_iter()
+                .filter(|m| {
+                    m.extract_castling() && (m.extract_target() as u8 & 0b111 == 6) == kingside
+                })
+                .collect()
+        } else {
+            let (piece_index, rest) = match text.chars().next() {
+                Some(ch) if ch.is_ascii_uppercase() => (
+                    PIECE_LETTERS[1..]
+                        .iter()
+                        .position(|&letter| letter == ch)
+                        .ok_or_else(syntax_error)?
+                        + 1,
+                    &text[1..],
+                ),
+                _ => (0, text),
+            };
+            // Promotion suffix, with or without '='
+            let (rest, promoted) = match rest.char_indices().last() {
+                Some((i, ch)) if piece_index == 0 && "NBRQ".contains(ch) => (
+                    rest[..i].strip_suffix('=').unwrap_or(&rest[..i]),
+                    PIECE_LETTERS.iter().position(|&letter| letter == ch),
+                ),
+                _ => (rest, None),
+            };
+            if rest.len() < 2 || !rest.is_char_boundary(rest.len() - 2) {
+                return Err(syntax_error());
+            }
+            let (from, target) = rest.split_at(rest.len() - 2);
+            let target_square = Square::try_from(target).map_err(|_| syntax_error())?;
+            let from = from.strip_suffix('x').unwrap_or(from);
+            // Disambiguation by file, rank or both
+            let (from_file, from_rank) = match from.as_bytes() {
+                [] => (None, None),
+                [file @ b'a'..=b'h'] => (Some(file - b'a'), None),
+                [rank @ b'1'..=b'8'] => (None, Some(rank - b'1')),
+                [file @ b'a'..=b'h', rank @ b'1'..=b'8'] => (Some(file - b'a'), Some(rank - b'1')),
+                _ => return Err(syntax_error()),
+            };
+
+            moves
+                .into_iter()
+                .filter(|m| {
+                    let (file, rank) = <(u8, u8)>::from(m.extract_source());
+                    let promotion = m.extract_promoted_piece();
+                    piece_type(m.extract_piece()) == piece_index
+                        && !m.extract_castling()
+                        && m.extract_target() == target_square
+                        && from_file.is_none_or(|f| f == file)
+                        && from_rank.is_none_or(|r| r == rank)
+                        && match promoted {
+                            Some(index) => {
+                                promotion != Piece::None && piece_type(promotion) == index
+                            }
+                            None => promotion == Piece::None,
+                        }
+                })
+                .collect()
+        };
+
+        match matches.as_slice() {
+            [mov] => Ok(*mov),
+            [] => Err(SanError::IllegalMove(san.to_string())),
+            _ => Err(SanError::Ambiguous(san.to_string())),
+        }
+    }
+
+    /// `parse_san_with` using the global `ATTACKS` tables
+    #[inline]
+    pub fn parse_san(&self, san: &str) -> Result<Move, SanError> {
+        self.parse_san_with(san, &ATTACKS)
+    }
+}
+
+#[inline]
+fn file_char(square: Square) -> char {
+    (b'a' + (square as u8 & 0b111)) as char
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        attacks::{Attacks, ATTACKS},
+        move_gen::generate_legal_moves,
+        position::Position,
+        utils::fen::{parse_fen, KILLER_POSITION, START_POSITION, TRICKY_POSITION},
+    };
+
+    use super::SanError;
+
+    fn check_round_trip(pos: &mut Position, attacks: &Attacks, depth: u32) {
+        for mov in generate_legal_moves(attacks, pos) {
+            let san = pos.move_to_san_with(mov, attacks);
+            assert!(
+                pos.parse_san_with(&san, attacks) == Ok(mov),
+                "{} as {} in {}",
+                mov,
+                san,
+                pos.to_fen()
+            );
+            if depth > 1 {
+                let undo = pos.make_move_unchecked(mov);
+                check_round_trip(pos, attacks, depth - 1);
+                pos.unmake_move(mov, undo);
+            }
+        }
+    }
+
+    fn san_of(fen: &str, uci: &str, attacks: &Attacks) -> String {
+        let pos = parse_fen(fen).unwrap();
+        let mov = generate_legal_moves(attacks, &pos)
+            .into_iter()
+            .find(|m| m.to_string() == uci)
+            .unwrap();
+        pos.move_to_san_with(mov, attacks)
+    }
+
+    #[test]
+    fn test_san_round_trip() {
+        for fen in [START_POSITION, TRICKY_POSITION, KILLER_POSITION] {
+            check_round_trip(&mut parse_fen(fen).unwrap(), &ATTACKS, 2);
+        }
+    }
+
+    #[test]
+    fn test_move_to_san() {
+        let cases = [
+            (START_POSITION, "g1f3", "Nf3"),
+            (START_POSITION, "e2e4", "e4"),
+            (TRICKY_POSITION, "e1g1", "O-O"),
+            (TRICKY_POSITION, "e1c1", "O-O-O"),
+            (TRICKY_POSITION, "e2a6", "Bxa6"),
+            (TRICKY_POSITION, "d5e6", "dxe6"),
+            // Disambiguation by file, rank and both
+            ("4k3/8/8/8/8/5N2/8/1N2K3 w - - 0 1", "b1d2", "Nbd2"),
+            ("4k3/8/8/R7/8/8/8/R3K3 w - - 0 1", "a1a3", "R1a3"),
+            ("8/8/k7/8/4Q2Q/8/8/K6Q w - - 0 1", "h4e1", "Qh4e1"),
+            ("8/8/k7/8/4Q2Q/8/8/K6Q w - - 0 1", "e4e1", "Qee1"),
+            // Promotions and checks
+            ("k7/4P3/8/8/8/8/8/4K3 w - - 0 1", "e7e8q", "e8=Q+"),
+            ("k7/4P3/8/8/8/8/8/4K3 w - - 0 1", "e7e8n", "e8=N"),
+            (
+                "rnbqkbnr/pppp1ppp/8/4p3/6P1/5P2/PPPPP2P/RNBQKBNR b KQkq g3 0 2",
+                "d8h4",
+                "Qh4#",
+            ),
+        ];
+        for (fen, uci, san) in cases {
+            assert_eq!(san_of(fen, uci, &ATTACKS), san, "{} in {}", uci, fen);
+        }
+    }
+
+    #[test]
+    fn test_parse_san() {
+        let pos = parse_fen(TRICKY_POSITION).unwrap();
+        for (san, uci) in [
+            ("0-0", "e1g1"),
+            ("O-O-O", "e1c1"),
+            ("Bxa6!", "e2a6"),
+            ("Ba6", "e2a6"),
+            ("Nxf7", "e5f7"),
+            ("Qxf6", "f3f6"),
+        ] {
+            assert_eq!(pos.parse_san(san).unwrap().to_string(), uci);
+        }
+        let pos = parse_fen("k7/4P3/8/8/8/8/8/4K3 w - - 0 1").unwrap();
+        assert_eq!(pos.parse_san("e8Q+").unwrap().to_string(), "e7e8q");
+
+        let pos = parse_fen("4k3/8/8/8/8/5N2/8/1N2K3 w - - 0 1").unwrap();
+        for (san, error) in [
+            ("Nd2", SanError::Ambiguous("Nd2".to_string())),
+            ("Nd3", SanError::IllegalMove("Nd3".to_string())),
+            ("Zd2", SanError::Syntax("Zd2".to_string())),
+            ("N", SanError::Syntax("N".to_string())),
+        ] {
+            assert!(pos.parse_san(san) == Err(error), "{}", san);
+        }
+    }
+}
@@ -1,137 +1,576 @@
 use std::{
-    convert::TryFrom,
+    fmt::Display,
     io::{stdin, BufRead},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
 };
 
 use crate::{
-    attacks::Attacks,
+    attacks::{Attacks, ATTACKS},
+    history::GameHistory,
     move_gen,
     move_list::{Move, MoveList},
-    position::Position,
-    search::search_pos,
-    square::Square,
-    utils::fen::{parse_fen, EMPTY_BOARD, START_POSITION},
+    position::{Position, Violation},
+    search::{search_pos, SearchLimits},
+    side::Side,
+    transposition::{TranspositionTable, DEFAULT_HASH_MB},
+    utils::fen::{parse_fen, FenError, START_POSITION},
 };
 
+/// Reasons a UCI position command can be rejected
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PositionError {
+    /// Not followed by 'startpos' or 'fen'
+    InvalidCommand,
+    Fen(FenError),
+    /// A move that is not in coordinate notation or that no piece can make
+    InvalidMove(String),
+    /// A move that leaves the king in check
+    IllegalMove(String),
+    /// The position breaks rules checked by `Position::validate`
+    Invalid(Vec<Violation>),
+}
+
+impl Display for PositionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PositionError::InvalidCommand => write!(f, "expected 'startpos' or 'fen'"),
+            PositionError::Fen(err) => write!(f, "invalid fen: {}", err),
+            PositionError::InvalidMove(mov) => write!(f, "invalid move '{}'", mov),
+            PositionError::IllegalMove(mov) => write!(f, "illegal move '{}'", mov),
+            PositionError::Invalid(violations) => {
+                for (i, violation) in violations.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", violation)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for PositionError {}
+
+impl From<FenError> for PositionError {
+    fn from(err: FenError) -> Self {
+        PositionError::Fen(err)
+    }
+}
+
+/// Largest transposition table `setoption name Hash` accepts, in megabytes
+pub const MAX_HASH_MB: usize = 65536;
+
+/// Engine settings changed with `setoption`
+#[derive(Clone, Debug)]
+pub struct UciOptions {
+    /// Reject positions that fail `Position::validate`
+    pub validate_positions: bool,
+    /// Write and read castling moves as the king capturing its own rook
+    pub chess960: bool,
+    /// Size of the transposition table in megabytes
+    pub hash_mb: usize,
+}
+
+impl UciOptions {
+    pub fn print_options(&self) {
+        println!(
+            "option name ValidatePositions type check default {}",
+            self.validate_positions
+        );
+        println!(
+            "option name UCI_Chess960 type check default {}",
+            self.chess960
+        );
+        println!(
+            "option name Hash type spin default {} min 1 max {}",
+            self.hash_mb, MAX_HASH_MB
+        );
+    }
+}
+
+impl Default for UciOptions {
+    fn default() -> Self {
+        UciOptions {
+            validate_positions: true,
+            chess960: false,
+            hash_mb: DEFAULT_HASH_MB,
+        }
+    }
+}
+
 pub fn uci_loop() {
-    let attacks = Attacks::gen();
-    let mut pos = parse_fen(EMPTY_BOARD).unwrap();
+    let mut options = UciOptions::default();
+    let mut pos = parse_fen(START_POSITION).unwrap();
+    let mut history = GameHistory::new();
+    // Shared with the search thread, which holds the lock while it searches
+    let tt = Arc::new(Mutex::new(TranspositionTable::new(options.hash_mb)));
+    let stop = Arc::new(AtomicBool::new(false));
+    let mut search = None;
     println!("id name ARCE");
     println!("id name Ian Smith");
+    options.print_options();
     println!("uciok");
 
     let mut input = String::new();
     loop {
         input.clear();
         // Get user/GUI input
-        if stdin().lock().read_line(&mut input).is_err() {
-            continue;
+        match stdin().lock().read_line(&mut input) {
+            // The GUI has closed the pipe, so there will be no quit command
+            Ok(0) => input.push_str("quit"),
+            Ok(_) => {}
+            Err(_) => continue,
         }
 
+        // These are answered straight away, even while searching
         if input.starts_with("isready") {
             println!("readyok");
             continue;
-        } else if input.starts_with("position") {
-            if let Some(p) = parse_position(&input, &attacks) {
-                pos = p;
+        } else if input.starts_with("stop") {
+            stop.store(true, Ordering::Relaxed);
+            wait_for_search(&mut search);
+            continue;
+        } else if input.starts_with("quit") {
+            stop.store(true, Ordering::Relaxed);
+            wait_for_search(&mut search);
+            break;
+        }
+
+        // Anything else changes what a search would use, so waits for it to finish
+        wait_for_search(&mut search);
+        if input.starts_with("position") {
+            match parse_position(input.trim_end(), &ATTACKS, &mut history, &options) {
+                Ok(p) => pos = p,
+                Err(err) => println!("info string invalid position: {}", err),
+            }
+        } else if input.starts_with("setoption") {
+            let hash_mb = options.hash_mb;
+            if parse_setoption(input.trim_end(), &mut options).is_none() {
+                println!("info string invalid option: {}", input.trim_end());
+            } else if options.hash_mb != hash_mb {
+                if let Err(err) = tt.lock().unwrap().resize(options.hash_mb) {
+                    println!("info string {}", err);
+                    options.hash_mb = hash_mb;
+                }
             }
         } else if input.starts_with("ucinewgame") {
-            pos = parse_position("position startpos", &attacks).unwrap();
+            pos = parse_position("position startpos", &ATTACKS, &mut history, &options).unwrap();
+            tt.lock().unwrap().clear();
         } else if input.starts_with("go") {
-            let _ = parse_go(&mut pos, &attacks, &input);
-        } else if input.starts_with("quit") {
-            break;
+            stop.store(false, Ordering::Relaxed);
+            search = parse_go(&pos, &tt, &history, &options, input.trim_end(), &stop);
+            if search.is_none() {
+                println!("info string invalid go command: {}", input.trim_end());
+            }
         } else if input.starts_with("uci") {
             println!("id name ARCE");
             println!("id name Ian Smith");
+            options.print_options();
             println!("uciok");
         }
     }
 }
 
-pub fn parse_move(move_str: &str, move_list: &MoveList) -> Result<Move, ()> {
-    if move_str.len() >= 4 {
-        let source_square = Square::try_from(&move_str[..2])?;
-        let target_square = Square::try_from(&move_str[2..4])?;
-        let promoted = if move_str.len() == 5 {
-            move_str.as_bytes()[4] as char
-        } else {
-            '-'
-        };
+/// Waits for a search started by `parse_go` to print its best move
+fn wait_for_search(search: &mut Option<JoinHandle<()>>) {
+    if let Some(handle) = search.take() {
+        handle.join().expect("search thread panicked");
+    }
+}
 
-        for mov in move_list.moves.iter() {
-            if source_square == mov.extract_source()
-                && target_square == mov.extract_target()
-                && promoted == mov.extract_promoted_piece().to_ascii().to_ascii_lowercase()
-            {
-                return Ok(*mov);
+/// Parses a UCI setoption command. Returns None if the option or value is not recognised.
+pub fn parse_setoption(uci_str: &str, options: &mut UciOptions) -> Option<()> {
+    let option_str = uci_str.strip_prefix("setoption name ")?;
+    let (name, value) = option_str.split_once(" value ")?;
+    match name.trim() {
+        "ValidatePositions" => options.validate_positions = value.trim().parse().ok()?,
+        "UCI_Chess960" => options.chess960 = value.trim().parse().ok()?,
+        "Hash" => {
+            let mb = value.trim().parse().ok()?;
+            if !(1..=MAX_HASH_MB).contains(&mb) {
+                return None;
             }
+            options.hash_mb = mb;
         }
+        _ => return None,
+    }
+    Some(())
+}
+
+/// Formats a move in UCI coordinate notation. In Chess960 castling is written as the king
+/// capturing its own rook, otherwise as the king moving two squares.
+pub fn format_move(mov: Move, pos: &Position, chess960: bool) -> String {
+    if chess960 && mov.extract_castling() {
+        let kingside = mov.extract_target() as u8 & 0b111 == 6;
+        format!(
+            "{}{}",
+            mov.extract_source(),
+            pos.castle.rook_square(pos.side, kingside)
+        )
+    } else {
+        mov.to_string()
     }
-    Err(())
 }
 
-pub fn parse_position(uci_str: &str, attacks: &Attacks) -> Option<Position> {
+/// Finds the move from `move_list` that `move_str` names in UCI coordinate notation
+#[allow(clippy::result_unit_err)]
+pub fn parse_move(
+    move_str: &str,
+    pos: &Position,
+    move_list: &MoveList,
+    chess960: bool,
+) -> Result<Move, ()> {
+    move_list
+        .iter()
+        .copied()
+        .find(|&mov| format_move(mov, pos, chess960) == move_str)
+        .ok_or(())
+}
+
+/// Parses a UCI position command. `history` is filled with the positions played since the last
+/// irreversible move so that the search can detect repetitions.
+pub fn parse_position(
+    uci_str: &str,
+    attacks: &Attacks,
+    history: &mut GameHistory,
+    options: &UciOptions,
+) -> Result<Position, PositionError> {
     // Check if string has correct prefix
-    let pos_str = uci_str.strip_prefix("position ")?;
+    let pos_str = uci_str
+        .strip_prefix("position ")
+        .ok_or(PositionError::InvalidCommand)?;
     // Check whether position is standard start position or FEN
-    if let Some(moves_str) = pos_str.strip_prefix("startpos") {
+    let (mut pos, moves_str) = if let Some(moves_str) = pos_str.strip_prefix("startpos") {
         // Create start position
-        let mut pos = parse_fen(START_POSITION)?;
-        // Check for moves
-        if let Some(moves_str) = moves_str.strip_prefix(" moves ") {
-            // Make moves
-            for mov in moves_str.split_ascii_whitespace() {
-                if let Ok(mov) = parse_move(mov, &move_gen::generate_moves(attacks, &pos)) {
-                    if !pos.make_move(mov, attacks) {
-                        return None;
-                    }
-                } else {
-                    return None;
-                }
-            }
-        }
-
-        Some(pos)
+        (
+            parse_fen(START_POSITION)?,
+            moves_str.strip_prefix(" moves "),
+        )
     } else if let Some(pos_str) = pos_str.strip_prefix("fen ") {
         // Check for moves
         match pos_str.split_once(" moves ") {
-            Some((fen, moves_str)) => {
-                // Create position
-                let mut pos = parse_fen(fen)?;
-                // Make moves
-                for mov in moves_str.split_ascii_whitespace() {
-                    if let Ok(mov) = parse_move(mov, &move_gen::generate_moves(attacks, &pos)) {
-                        if !pos.make_move(mov, attacks) {
-                            return None;
-                        }
-                    } else {
-                        return None;
-                    }
-                }
+            Some((fen, moves_str)) => (parse_fen(fen)?, Some(moves_str)),
+            None => (parse_fen(pos_str)?, None),
+        }
+    } else {
+        return Err(PositionError::InvalidCommand);
+    };
+
+    if options.validate_positions {
+        let violations = pos.validate(attacks);
+        if !violations.is_empty() {
+            return Err(PositionError::Invalid(violations));
+        }
+    }
 
-                Some(pos)
+    let mut game_history = GameHistory::new();
+    // Make moves
+    for move_str in moves_str.unwrap_or("").split_ascii_whitespace() {
+        let move_list = move_gen::generate_legal_moves(attacks, &pos);
+        let mov = match parse_move(move_str, &pos, &move_list, options.chess960) {
+            Ok(mov) => mov,
+            // Tell moves into check apart from moves that are not possible at all
+            Err(()) => {
+                let pseudo_legal = move_gen::generate_moves(attacks, &pos);
+                return Err(
+                    if parse_move(move_str, &pos, &pseudo_legal, options.chess960).is_ok() {
+                        PositionError::IllegalMove(move_str.to_string())
+                    } else {
+                        PositionError::InvalidMove(move_str.to_string())
+                    },
+                );
             }
-            None => parse_fen(pos_str),
+        };
+        let hash = pos.hash;
+        pos.make_move_unchecked(mov);
+        // Earlier positions cannot be repeated after an irreversible move
+        if pos.halfmove_clock == 0 {
+            game_history.clear();
+        } else {
+            game_history.push(hash);
         }
-    } else {
-        None
     }
+
+    *history = game_history;
+    Ok(pos)
+}
+
+/// Parses a UCI go command and starts searching the position on another thread, which prints
+/// the best move once a limit is reached or `stop` is set. Returns None if the command is not
+/// understood, in which case nothing is searched.
+pub fn parse_go(
+    position: &Position,
+    tt: &Arc<Mutex<TranspositionTable>>,
+    history: &GameHistory,
+    options: &UciOptions,
+    go_str: &str,
+    stop: &Arc<AtomicBool>,
+) -> Option<JoinHandle<()>> {
+    let limits = parse_search_limits(go_str, position.side)?;
+    let mut position = position.clone();
+    let history = history.clone();
+    let chess960 = options.chess960;
+    let (tt, stop) = (Arc::clone(tt), Arc::clone(stop));
+    Some(thread::spawn(move || {
+        let mut tt = tt.lock().unwrap();
+        let best_move = search_pos(
+            &mut position,
+            &ATTACKS,
+            &mut tt,
+            &limits,
+            &history,
+            chess960,
+            stop,
+        );
+        println!("bestmove {}", format_move(best_move, &position, chess960));
+    }))
 }
 
-pub fn parse_go(position: &mut Position, attacks: &Attacks, go_str: &str) -> Option<()> {
+/// Parses the limits of a UCI go command for `side` to move. With a clock instead of a fixed
+/// move time, the search gets an even share of the remaining time plus half the increment.
+/// Returns None if the value of a limit is missing or not a number. Arguments that are not
+/// supported, such as `ponder`, `searchmoves` and `mate`, are ignored, and `infinite` searches
+/// until told to stop as a search without limits does.
+pub fn parse_search_limits(go_str: &str, side: Side) -> Option<SearchLimits> {
     let mut go_args = go_str.split_ascii_whitespace();
     if go_args.next()? != "go" {
         return None;
     }
-    let mut depth = 6;
-    // Check for fixed depth
-    if go_args.next()? == "depth" {
-        match go_args.next()?.parse::<u8>() {
-            Ok(d) => depth = d,
-            Err(_) => (),
+    let mut limits = SearchLimits::default();
+    let (mut time, mut inc, mut moves_to_go) = (None, 0, 30);
+    while let Some(arg) = go_args.next() {
+        let mut value = || -> Option<u64> { go_args.next()?.parse().ok() };
+        match (arg, side) {
+            ("depth", _) => limits.depth = Some(value()?.min(u8::MAX as u64) as u8),
+            ("nodes", _) => limits.nodes = Some(value()?),
+            ("movetime", _) => limits.movetime = Some(Duration::from_millis(value()?)),
+            ("wtime", Side::White) | ("btime", Side::Black) => time = Some(value()?),
+            ("winc", Side::White) | ("binc", Side::Black) => inc = value()?,
+            ("movestogo", _) => moves_to_go = value()?.max(1),
+            ("wtime", _) | ("btime", _) | ("winc", _) | ("binc", _) => {
+                value()?;
+            }
+            _ => {}
         }
     }
-    search_pos(position, attacks, depth);
-    None
+    if let (None, Some(time)) = (limits.movetime, time) {
+        // Keep a little back for communication delays
+        let budget = (time / moves_to_go + inc / 2).min(time.saturating_sub(50));
+        limits.movetime = Some(Duration::from_millis(budget));
+    }
+    Some(limits)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        attacks::ATTACKS, history::GameHistory, piece::Piece, position::Violation, side::Side,
+        square::Square, utils::fen::FenError,
+    };
+    use std::time::Duration;
+
+    use super::{
+        parse_position, parse_search_limits, parse_setoption, PositionError, SearchLimits,
+        UciOptions,
+    };
+
+    #[test]
+    fn test_parse_position_history() {
+        let mut history = GameHistory::new();
+
+        let pos = parse_position(
+            "position startpos moves g1f3 g8f6 f3g1 f6g8 g1f3 g8f6 f3g1 f6g8",
+            &ATTACKS,
+            &mut history,
+            &UciOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(history.len(), 8);
+        assert!(history.is_threefold(&pos));
+
+        // Pawn moves drop positions that can no longer repeat
+        let pos = parse_position(
+            "position startpos moves g1f3 g8f6 f3g1 f6g8 e2e4 b8c6",
+            &ATTACKS,
+            &mut history,
+            &UciOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(history.len(), 1);
+        assert!(!history.is_repetition(&pos));
+
+        // A failed parse leaves the history untouched
+        assert!(parse_position(
+            "position startpos moves e2e5",
+            &ATTACKS,
+            &mut history,
+            &UciOptions::default()
+        )
+        .is_err());
+        assert_eq!(history.len(), 1);
+    }
+
+    #[test]
+    fn test_parse_position_errors() {
+        let mut history = GameHistory::new();
+        let options = UciOptions::default();
+        let mut parse =
+            |uci_str: &str| parse_position(uci_str, &ATTACKS, &mut history, &options).unwrap_err();
+
+        assert_eq!(parse("position start"), PositionError::InvalidCommand);
+        assert_eq!(
+            parse("position fen 8/8/8/8 w - - 0 1"),
+            PositionError::Fen(FenError::RankCount { offset: 7 })
+        );
+        assert_eq!(
+            parse("position startpos moves e2e4 e7e6 e4e6"),
+            PositionError::InvalidMove("e4e6".to_string())
+        );
+        assert_eq!(
+            parse("position fen 4k3/8/8/8/8/8/4r3/4K3 w - - 0 1 moves e1f2"),
+            PositionError::IllegalMove("e1f2".to_string())
+        );
+    }
+
+    #[test]
+    fn test_validate_positions_option() {
+        let mut history = GameHistory::new();
+        let mut options = UciOptions::default();
+        let uci_str = "position fen 4k3/8/8/8/8/8/8/4K2R w KQ - 0 1";
+
+        assert_eq!(
+            parse_position(uci_str, &ATTACKS, &mut history, &options).unwrap_err(),
+            PositionError::Invalid(vec![Violation::CastleRight('Q')])
+        );
+        assert!(
+            parse_setoption("setoption name ValidatePositions value false", &mut options).is_some()
+        );
+        assert!(parse_position(uci_str, &ATTACKS, &mut history, &options).is_ok());
+        assert!(parse_setoption("setoption name Unknown value 1", &mut options).is_none());
+    }
+
+    #[test]
+    fn test_chess960_castling_notation() {
+        let mut history = GameHistory::new();
+        let mut options = UciOptions::default();
+        let moves = "position startpos moves e2e4 e7e5 g1f3 b8c6 f1c4 g8f6";
+
+        // Standard chess writes castling as a two square king move
+        let pos =
+            parse_position(&format!("{} e1g1", moves), &ATTACKS, &mut history, &options).unwrap();
+        assert_eq!(pos.piece_on(Square::F1), Some(Piece::WRook));
+
+        // Chess960 writes it as the king capturing its own rook
+        assert!(parse_setoption("setoption name UCI_Chess960 value true", &mut options).is_some());
+        assert_eq!(
+            parse_position(&format!("{} e1g1", moves), &ATTACKS, &mut history, &options)
+                .unwrap_err(),
+            PositionError::InvalidMove("e1g1".to_string())
+        );
+        let pos =
+            parse_position(&format!("{} e1h1", moves), &ATTACKS, &mut history, &options).unwrap();
+        assert_eq!(pos.piece_on(Square::G1), Some(Piece::WKing));
+        assert_eq!(pos.piece_on(Square::F1), Some(Piece::WRook));
+
+        // The king may already stand on its destination, or land where the rook was
+        let pos = parse_position(
+            "position fen b1q1rrkb/pppppppp/3nn3/8/P7/1PPP4/4PPPP/BQNNRKRB w GE - 1 9 moves f1g1",
+            &ATTACKS,
+            &mut history,
+            &options,
+        )
+        .unwrap();
+        assert_eq!(pos.piece_on(Square::G1), Some(Piece::WKing));
+        assert_eq!(pos.piece_on(Square::F1), Some(Piece::WRook));
+        assert_eq!(
+            pos.to_fen(),
+            "b1q1rrkb/pppppppp/3nn3/8/P7/1PPP4/4PPPP/BQNNRRKB b - - 2 9"
+        );
+    }
+    #[test]
+    fn test_parse_setoption() {
+        let mut options = UciOptions::default();
+        assert!(parse_setoption("setoption name Hash value 64", &mut options).is_some());
+        assert_eq!(options.hash_mb, 64);
+        assert!(parse_setoption("setoption name Hash value 0", &mut options).is_none());
+        assert!(parse_setoption("setoption name Hash value lots", &mut options).is_none());
+        assert_eq!(options.hash_mb, 64);
+
+        assert!(
+            parse_setoption("setoption name ValidatePositions value false", &mut options).is_some()
+        );
+        assert!(!options.validate_positions);
+        assert!(parse_setoption("setoption name Threads value 2", &mut options).is_none());
+    }
+
+    #[test]
+    fn test_parse_search_limits() {
+        let limits = |go_str: &str, side: Side| parse_search_limits(go_str, side);
+        let millis = |ms: u64| Some(Duration::from_millis(ms));
+
+        assert_eq!(limits("go", Side::White), Some(SearchLimits::default()));
+        assert_eq!(
+            limits("go infinite", Side::White),
+            Some(SearchLimits::default())
+        );
+        assert_eq!(
+            limits("go depth 7 nodes 100000", Side::Black),
+            Some(SearchLimits {
+                depth: Some(7),
+                nodes: Some(100000),
+                movetime: None,
+            })
+        );
+        assert_eq!(
+            limits("go movetime 1500", Side::White).unwrap().movetime,
+            millis(1500)
+        );
+        // An even share of the clock of the side to move, plus half the increment
+        assert_eq!(
+            limits("go wtime 60000 btime 30000 winc 1000 binc 0", Side::White)
+                .unwrap()
+                .movetime,
+            millis(2500)
+        );
+        assert_eq!(
+            limits("go wtime 60000 btime 30000 movestogo 10", Side::Black)
+                .unwrap()
+                .movetime,
+            millis(3000)
+        );
+        assert_eq!(
+            limits("go wtime 40 btime 40", Side::White)
+                .unwrap()
+                .movetime,
+            millis(0)
+        );
+
+        // Arguments that are not supported are skipped, along with their values
+        assert_eq!(
+            limits("go ponder", Side::White),
+            Some(SearchLimits::default())
+        );
+        assert_eq!(
+            limits("go mate 3", Side::White),
+            Some(SearchLimits::default())
+        );
+        assert_eq!(
+            limits("go searchmoves e2e4 d2d4 depth 5", Side::White),
+            Some(SearchLimits {
+                depth: Some(5),
+                ..SearchLimits::default()
+            })
+        );
+
+        assert_eq!(limits("go depth", Side::White), None);
+        assert_eq!(limits("go depth x", Side::White), None);
+        assert_eq!(limits("go btime", Side::White), None);
+        assert_eq!(limits("stop", Side::White), None);
+    }
 }
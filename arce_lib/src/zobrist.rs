@@ -0,0 +1,75 @@
+use crate::{castle_rights::CastleRights, piece::Piece, square::Square, utils::random::Random};
+
+/// Random keys used to build [Zobrist hashes](https://www.chessprogramming.org/Zobrist_Hashing)
+/// of positions. Generated at compile time from the engine's xor shift generator so that hashes
+/// are identical between runs.
+pub static ZOBRIST: ZobristKeys = ZobristKeys::gen();
+
+pub struct ZobristKeys {
+    pieces: [[u64; 64]; 12],
+    en_passant: [u64; 64],
+    castle: [u64; 16],
+    side: u64,
+}
+
+impl ZobristKeys {
+    const fn gen() -> ZobristKeys {
+        let mut rand = Random::new();
+
+        let mut pieces = [[0; 64]; 12];
+        let mut piece = 0;
+        while piece < 12 {
+            let mut square = 0;
+            while square < 64 {
+                pieces[piece][square] = rand.rand_u64();
+                square += 1;
+            }
+            piece += 1;
+        }
+
+        let mut en_passant = [0; 64];
+        let mut square = 0;
+        while square < 64 {
+            en_passant[square] = rand.rand_u64();
+            square += 1;
+        }
+
+        let mut castle = [0; 16];
+        let mut index = 0;
+        while index < 16 {
+            castle[index] = rand.rand_u64();
+            index += 1;
+        }
+
+        let side = rand.rand_u64();
+
+        ZobristKeys {
+            pieces,
+            en_passant,
+            castle,
+            side,
+        }
+    }
+
+    #[inline]
+    pub fn piece(&self, piece: Piece, square: Square) -> u64 {
+        debug_assert_ne!(piece, Piece::None);
+        self.pieces[piece as usize][square as usize]
+    }
+
+    #[inline]
+    pub fn en_passant(&self, square: Square) -> u64 {
+        self.en_passant[square as usize]
+    }
+
+    #[inline]
+    pub fn castle(&self, castle: CastleRights) -> u64 {
+        self.castle[castle.bits() as usize]
+    }
+
+    /// Key xored in when black is to move
+    #[inline]
+    pub const fn side(&self) -> u64 {
+        self.side
+    }
+}
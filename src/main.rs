@@ -1,19 +1,8 @@
-use std::time::Instant;
-
-use arce_lib::{
-    attacks::Attacks,
-    utils::{
-        fen::{parse_fen, START_POSITION, TRICKY_POSITION},
-        uci::{parse_go, parse_move, parse_position, uci_loop},
-    },
-};
+use arce_lib::utils::{fen::parse_fen, uci::uci_loop};
 
 fn main() {
     // let now = Instant::now();
 
-    // let at = Attacks::gen();
-    // let mg = MoveGen::new(at);
-
     // match parse_position(
     //     "position startpos moves e2e4 e7e5 f1d3 f8b4 g1e2 g8f6 e1g1 e8g8 f2f4",
     //     &mg,
@@ -1,5 +1,250 @@
-use arce_lib::{perft, utils::fen};
+use std::{env, fs, process, thread, time::Instant};
+
+use arce_lib::{
+    attacks::ATTACKS,
+    perft::{perft_divide, perft_divide_stats, perft_fast, PerftOptions, PerftStats},
+    position::Position,
+    utils::{
+        epd::parse_perft_epd,
+        fen::{parse_fen, START_POSITION},
+    },
+};
+
+const USAGE: &str = "\
+Usage: perft [OPTIONS] <DEPTH> [FEN]
+       perft [OPTIONS] --suite <FILE>
+
+Counts the leaf nodes of the move tree of FEN (the start position by default) to DEPTH.
+With --suite, checks every position of a perft suite file, with lines such as
+`<FEN> ;D1 20 ;D2 400`, and exits with status 1 if any count is wrong.
+
+Options:
+    --divide          Print the nodes below each root move
+    --stats           Count captures, en passant, castles, promotions, checks and mates
+    --threads <N>     Threads to split the root moves between (default: one per CPU)
+    --hash <MB>       Size of the perft hash table (default: 64, 0 to disable)
+    --suite <FILE>    Run a perft suite file instead of a single position
+    --max-depth <N>   Skip suite entries deeper than N
+    -h, --help        Print this message";
+
+struct Args {
+    depth: Option<u32>,
+    fen: String,
+    divide: bool,
+    stats: bool,
+    suite: Option<String>,
+    max_depth: u32,
+    options: PerftOptions,
+}
 
 fn main() {
-    perft::perft_test(fen::parse_fen(fen::TRICKY_POSITION).unwrap(), 6);
+    let args = match parse_args(env::args().skip(1)) {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("error: {}\n\n{}", err, USAGE);
+            process::exit(2);
+        }
+    };
+
+    let passed = match &args.suite {
+        Some(path) => run_suite(path, &args),
+        None => run_position(&args),
+    };
+    if !passed {
+        process::exit(1);
+    }
+}
+
+fn parse_args(mut argv: impl Iterator<Item = String>) -> Result<Args, String> {
+    let mut args = Args {
+        depth: None,
+        fen: String::new(),
+        divide: false,
+        stats: false,
+        suite: None,
+        max_depth: u32::MAX,
+        options: PerftOptions {
+            threads: thread::available_parallelism().map_or(1, |n| n.get()),
+            hash_mb: 64,
+        },
+    };
+    let mut fen = Vec::new();
+
+    while let Some(arg) = argv.next() {
+        let mut value = |name: &str| argv.next().ok_or(format!("{} needs a value", name));
+        match arg.as_str() {
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                process::exit(0);
+            }
+            "--divide" => args.divide = true,
+            "--stats" => args.stats = true,
+            "--threads" => args.options.threads = parse_number(&arg, &value(&arg)?)?,
+            "--hash" => args.options.hash_mb = parse_number(&arg, &value(&arg)?)?,
+            "--suite" => args.suite = Some(value(&arg)?),
+            "--max-depth" => args.max_depth = parse_number(&arg, &value(&arg)?)?,
+            _ if arg.starts_with("--") => return Err(format!("unknown option {}", arg)),
+            _ if args.depth.is_none() => args.depth = Some(parse_number("depth", &arg)?),
+            // The FEN may be given quoted or as separate arguments
+            _ => fen.push(arg),
+        }
+    }
+
+    args.fen = if fen.is_empty() {
+        START_POSITION.to_string()
+    } else {
+        fen.join(" ")
+    };
+    if args.suite.is_some() {
+        if args.depth.is_some() {
+            return Err("a depth cannot be given with --suite".to_string());
+        }
+    } else if args.depth.is_none() {
+        return Err("missing depth".to_string());
+    }
+    if args.options.threads == 0 {
+        return Err("--threads must be at least 1".to_string());
+    }
+    Ok(args)
+}
+
+fn parse_number<T: std::str::FromStr>(name: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid {} '{}'", name, value))
+}
+
+/// Runs perft on the single position given on the command line
+fn run_position(args: &Args) -> bool {
+    let pos = match parse_fen(&args.fen) {
+        Ok(pos) => pos,
+        Err(err) => {
+            eprintln!("error: invalid FEN '{}': {}", args.fen, err);
+            return false;
+        }
+    };
+    if let Some(violations) = violations(&pos) {
+        eprintln!("error: invalid position '{}': {}", args.fen, violations);
+        return false;
+    }
+    let depth = args.depth.unwrap();
+
+    let start_time = Instant::now();
+    let nodes = if args.stats {
+        let mut total = PerftStats {
+            nodes: (depth == 0) as u64,
+            ..PerftStats::default()
+        };
+        for (mov, stats) in perft_divide_stats(&pos, depth, &args.options) {
+            if args.divide {
+                println!("{:5} {}", mov.to_string(), stats);
+            }
+            total += stats;
+        }
+        println!("{}", total);
+        total.nodes
+    } else {
+        let divide = perft_divide(&pos, depth, &args.options);
+        if args.divide {
+            for (mov, nodes) in divide.iter() {
+                println!("{:5} {}", mov.to_string(), nodes);
+            }
+        }
+        if depth == 0 {
+            1
+        } else {
+            divide.iter().map(|(_, nodes)| nodes).sum()
+        }
+    };
+    let time = start_time.elapsed();
+
+    println!("Depth: {}", depth);
+    println!("Nodes: {}", nodes);
+    println!("Time: {:.3?}", time);
+    println!("NPS: {:.0}", nodes as f64 / time.as_secs_f64());
+    true
+}
+
+/// Checks every depth of every position in a perft suite file, returning false on any wrong
+/// count or unreadable line
+fn run_suite(path: &str, args: &Args) -> bool {
+    let suite = match fs::read_to_string(path) {
+        Ok(suite) => suite,
+        Err(err) => {
+            eprintln!("error: cannot read {}: {}", path, err);
+            return false;
+        }
+    };
+
+    let mut positions = 0;
+    let mut failed = 0;
+    let mut total_nodes = 0;
+    let start_time = Instant::now();
+    for (number, line) in suite.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        positions += 1;
+        let entry = match parse_perft_epd(line) {
+            Ok(entry) => entry,
+            Err(err) => {
+                println!("FAIL line {}: {}", number + 1, err);
+                failed += 1;
+                continue;
+            }
+        };
+        if let Some(violations) = violations(&entry.pos) {
+            println!("FAIL line {}: {}: {}", number + 1, entry.fen, violations);
+            failed += 1;
+            continue;
+        }
+
+        let mut passed = true;
+        for &(depth, expected) in entry.depths.iter() {
+            if depth > args.max_depth {
+                continue;
+            }
+            let nodes = perft_fast(&entry.pos, depth, &args.options);
+            total_nodes += nodes;
+            if nodes != expected {
+                println!(
+                    "FAIL line {}: {} depth {}: expected {}, got {}",
+                    number + 1,
+                    entry.fen,
+                    depth,
+                    expected,
+                    nodes
+                );
+                passed = false;
+            }
+        }
+        if passed {
+            println!("ok   line {}: {}", number + 1, entry.fen);
+        } else {
+            failed += 1;
+        }
+    }
+    let time = start_time.elapsed();
+
+    println!(
+        "{} of {} positions passed, {} nodes in {:.3?} ({:.0} nps)",
+        positions - failed,
+        positions,
+        total_nodes,
+        time,
+        total_nodes as f64 / time.as_secs_f64()
+    );
+    failed == 0
+}
+
+/// Describes the rules a position breaks, or returns None if it is valid. Perft can panic or
+/// miscount on an invalid position, so such positions are reported as failures instead.
+fn violations(pos: &Position) -> Option<String> {
+    let violations = pos.validate(&ATTACKS);
+    if violations.is_empty() {
+        return None;
+    }
+    let violations: Vec<String> = violations.iter().map(|v| v.to_string()).collect();
+    Some(violations.join(", "))
 }